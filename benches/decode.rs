@@ -0,0 +1,80 @@
+//! Decoding throughput for representative ITM streams
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use itm::{
+    packet::{
+        ExceptionTrace, Function, Instrumentation, LocalTimestamp, PeriodicPcSample, GTS1, GTS2,
+    },
+    Decoder, Packet, SliceDecoder,
+};
+
+// number of packets in each stream
+const PACKETS: usize = 4096;
+
+fn encode(packets: impl IntoIterator<Item = Packet>) -> Vec<u8> {
+    packets.into_iter().flat_map(|p| p.to_bytes()).collect()
+}
+
+// `printf`-style logging: one byte per packet on stimulus port 0
+fn text() -> Vec<u8> {
+    let text = b"The quick brown fox jumps over the lazy dog\n";
+
+    encode(
+        text.iter()
+            .cycle()
+            .take(PACKETS)
+            .map(|&c| Packet::from(Instrumentation::new(0, &[c]))),
+    )
+}
+
+// application data interleaved with local and global timestamps, PC samples and exceptions
+fn mixed() -> Vec<u8> {
+    encode((0..PACKETS as u32).map(|i| match i % 8 {
+        0 | 4 => Instrumentation::new(1, &i.to_le_bytes()).into(),
+        1 | 5 => LocalTimestamp::new(i * 97 % (1 << 20), false, i % 16 == 1).into(),
+        2 => PeriodicPcSample::new(Some(0x0800_0000 + i * 4)).into(),
+        3 => ExceptionTrace::new(15, Function::Enter).into(),
+        6 => GTS1::new(i * 1021 % (1 << 26), false, false).into(),
+        _ => GTS2::new(u64::from(i), true).into(),
+    }))
+}
+
+// every byte is a reserved header, which is reported as an error
+fn reserved_headers() -> Vec<u8> {
+    vec![0b0000_0100; PACKETS]
+}
+
+fn decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(PACKETS as u64));
+
+    for (name, bytes) in [
+        ("text", text()),
+        ("mixed", mixed()),
+        ("reserved_headers", reserved_headers()),
+    ] {
+        group.bench_with_input(BenchmarkId::new("Decoder", name), &bytes, |b, bytes| {
+            b.iter(|| {
+                let mut decoder = Decoder::new();
+                let mut n = 0;
+                // same chunk size as `Stream`
+                for chunk in bytes.chunks(64) {
+                    decoder.feed(chunk);
+                    n += (&mut decoder).count();
+                }
+                assert_eq!(n, PACKETS);
+            })
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("SliceDecoder", name),
+            &bytes,
+            |b, bytes| b.iter(|| assert_eq!(SliceDecoder::new(bytes).count(), PACKETS)),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
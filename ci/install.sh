@@ -1,7 +1,10 @@
 set -euxo pipefail
 
 main() {
-    true
+    rustup target add thumbv7m-none-eabi wasm32-unknown-unknown
+
+    # checks that `ffi/include/itm.h` is up to date
+    cargo install cbindgen --version 0.29.4 --locked
 }
 
 main
@@ -2,9 +2,23 @@ set -euxo pipefail
 
 main() {
     cargo check --target $TARGET
+    cargo check --target $TARGET --no-default-features
+    cargo check --target $TARGET --no-default-features --features arbitrary
+    cargo check --target $TARGET --workspace
+    cargo check --target wasm32-unknown-unknown -p itm-wasm
+
+    # the C header matches the bindings
+    (cd ffi && cbindgen --config cbindgen.toml --verify --output include/itm.h)
+
+    # the `no_std` parser on an embedded target
+    cargo check --target thumbv7m-none-eabi --no-default-features
+    cargo check --target thumbv7m-none-eabi --no-default-features --features serde
 
     cargo test --target $TARGET
     cargo test --target $TARGET --release
+    cargo test --target $TARGET --features arbitrary,log,serde,test-util,tokio
+    cargo test --target $TARGET -p itm-ffi
+    cargo test --target $TARGET -p itm-wasm
 }
 
 main
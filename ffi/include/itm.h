@@ -0,0 +1,293 @@
+#ifndef ITM_H
+#define ITM_H
+
+/* Generated by cbindgen. Do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The action taken by the processor
+typedef enum ItmFunction {
+  // Entered exception
+  ItmFunction_Enter,
+  // Exited exception
+  ItmFunction_Exit,
+  // Returned to exception
+  ItmFunction_Return,
+} ItmFunction;
+
+// A push-based ITM packet decoder
+//
+// This is an opaque type; create it with `itm_decoder_new` and destroy it with `itm_decoder_free`
+typedef struct itm_decoder itm_decoder;
+
+// A decoded ITM packet or a decoding error
+//
+// See the documentation of the `itm` crate for the meaning of each field
+enum ItmPacket_Tag
+#if defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+  : uint8_t
+#endif // defined(__cplusplus) || __STDC_VERSION__ >= 202311L
+ {
+  // Overflow packet
+  ItmPacket_Overflow,
+  // Synchronization packet
+  ItmPacket_Synchronization,
+  // Instrumentation packet
+  ItmPacket_Instrumentation,
+  // Local timestamp packet
+  ItmPacket_LocalTimestamp,
+  // Global timestamp packet (format 1)
+  ItmPacket_Gts1,
+  // Global timestamp packet (format 2)
+  ItmPacket_Gts2,
+  // Stimulus Port Page (Extension packet)
+  ItmPacket_StimulusPortPage,
+  // Event Counter
+  ItmPacket_EventCounter,
+  // Exception Trace
+  ItmPacket_ExceptionTrace,
+  // Periodic PC Sample
+  ItmPacket_PeriodicPcSample,
+  // Data Trace PC Value
+  ItmPacket_DataTracePcValue,
+  // Data Trace Address
+  ItmPacket_DataTraceAddress,
+  // Data Trace Data Value
+  ItmPacket_DataTraceDataValue,
+  // Data Trace Match (ARMv8-M)
+  ItmPacket_DataTraceMatch,
+  // Other hardware source packet
+  ItmPacket_HardwareSource,
+  // Error: the packet starts with a reserved header byte
+  ItmPacket_ReservedHeader,
+  // Error: the packet doesn't adhere to the specification
+  ItmPacket_MalformedPacket,
+  // Error: the packet is an extension packet unknown to this library
+  ItmPacket_UnknownExtension,
+  // Error: the input ended in the middle of a packet
+  ItmPacket_Truncated,
+  // Error: a decoding error that has no representation in these bindings
+  ItmPacket_OtherError,
+};
+#ifndef __cplusplus
+#if __STDC_VERSION__ >= 202311L
+typedef enum ItmPacket_Tag ItmPacket_Tag;
+#else
+typedef uint8_t ItmPacket_Tag;
+#endif // __STDC_VERSION__ >= 202311L
+#endif // __cplusplus
+
+typedef struct ItmPacket_Synchronization_Body {
+  // The length in bytes of the packet
+  uint8_t len;
+} ItmPacket_Synchronization_Body;
+
+typedef struct ItmPacket_Instrumentation_Body {
+  // The stimulus port page of `port`
+  uint8_t page;
+  // The stimulus port that generated this packet, within its page
+  uint8_t port;
+  // The number of valid bytes in `payload` (1, 2 or 4)
+  uint8_t size;
+  // The payload of this packet
+  uint8_t payload[4];
+} ItmPacket_Instrumentation_Body;
+
+typedef struct ItmPacket_LocalTimestamp_Body {
+  // The interval since the previous local timestamp packet
+  uint32_t delta;
+  // The timestamp is delayed relative to the ITM or DWT data
+  bool timestamp_delayed;
+  // The ITM or DWT data is delayed relative to the associated event
+  bool event_delayed;
+} ItmPacket_LocalTimestamp_Body;
+
+typedef struct ItmPacket_Gts1_Body {
+  // Timestamp bits (up to 26 bits)
+  uint32_t bits;
+  // The clock change input has been asserted
+  bool clock_changed;
+  // The high-order bits of the global timestamp have changed
+  bool wrapped;
+} ItmPacket_Gts1_Body;
+
+typedef struct ItmPacket_Gts2_Body {
+  // High-order bits of the global timestamp
+  uint64_t bits;
+  // This is a 64-bit timestamp
+  bool is_64_bit;
+} ItmPacket_Gts2_Body;
+
+typedef struct ItmPacket_StimulusPortPage_Body {
+  // Stimulus port page
+  uint8_t page;
+} ItmPacket_StimulusPortPage_Body;
+
+typedef struct ItmPacket_EventCounter_Body {
+  // CPICNT wrapped around
+  bool cpi;
+  // EXCCNT wrapped around
+  bool exc;
+  // SLEEPCNT wrapped around
+  bool sleep;
+  // LSUCNT wrapped around
+  bool lsu;
+  // FOLDCNT wrapped around
+  bool fold;
+  // POSTCNT wrapped around
+  bool post;
+} ItmPacket_EventCounter_Body;
+
+typedef struct ItmPacket_ExceptionTrace_Body {
+  // Exception number
+  uint16_t number;
+  // The action taken by the processor
+  enum ItmFunction function;
+} ItmPacket_ExceptionTrace_Body;
+
+typedef struct ItmPacket_PeriodicPcSample_Body {
+  // The core is sleeping; `pc` is not valid
+  bool sleeping;
+  // Sampled PC
+  uint32_t pc;
+} ItmPacket_PeriodicPcSample_Body;
+
+typedef struct ItmPacket_DataTracePcValue_Body {
+  // Comparator that generated the data
+  uint8_t comparator;
+  // PC value of the instruction that caused the successful address comparison
+  uint32_t pc;
+} ItmPacket_DataTracePcValue_Body;
+
+typedef struct ItmPacket_DataTraceAddress_Body {
+  // Comparator that generated the data
+  uint8_t comparator;
+  // Data address that caused the successful address comparison
+  uint16_t address;
+} ItmPacket_DataTraceAddress_Body;
+
+typedef struct ItmPacket_DataTraceDataValue_Body {
+  // Comparator that generated the data
+  uint8_t comparator;
+  // This was a write access
+  bool write_access;
+  // The number of valid bytes in `value` (1, 2 or 4)
+  uint8_t size;
+  // Data value that caused the successful data value comparison
+  uint8_t value[4];
+} ItmPacket_DataTraceDataValue_Body;
+
+typedef struct ItmPacket_DataTraceMatch_Body {
+  // Comparator that generated the packet
+  uint8_t comparator;
+} ItmPacket_DataTraceMatch_Body;
+
+typedef struct ItmPacket_HardwareSource_Body {
+  // The discriminator, which identifies the type of the packet
+  uint8_t discriminator;
+  // The number of valid bytes in `payload` (1, 2 or 4)
+  uint8_t size;
+  // The payload of this packet
+  uint8_t payload[4];
+} ItmPacket_HardwareSource_Body;
+
+typedef struct ItmPacket_ReservedHeader_Body {
+  // The header byte
+  uint8_t byte;
+} ItmPacket_ReservedHeader_Body;
+
+typedef struct ItmPacket_MalformedPacket_Body {
+  // The header of the malformed packet
+  uint8_t header;
+  // Length of the malformed packet in bytes, including the header
+  uint8_t len;
+} ItmPacket_MalformedPacket_Body;
+
+typedef struct ItmPacket_UnknownExtension_Body {
+  // The header of the extension packet
+  uint8_t header;
+  // Length of the extension packet in bytes, including the header
+  uint8_t len;
+} ItmPacket_UnknownExtension_Body;
+
+typedef struct ItmPacket_Truncated_Body {
+  // The header of the truncated packet
+  uint8_t header;
+  // Number of bytes of the packet that were received, including the header
+  uint8_t len;
+} ItmPacket_Truncated_Body;
+
+typedef struct ItmPacket {
+  ItmPacket_Tag tag;
+  union {
+    ItmPacket_Synchronization_Body synchronization;
+    ItmPacket_Instrumentation_Body instrumentation;
+    ItmPacket_LocalTimestamp_Body local_timestamp;
+    ItmPacket_Gts1_Body gts1;
+    ItmPacket_Gts2_Body gts2;
+    ItmPacket_StimulusPortPage_Body stimulus_port_page;
+    ItmPacket_EventCounter_Body event_counter;
+    ItmPacket_ExceptionTrace_Body exception_trace;
+    ItmPacket_PeriodicPcSample_Body periodic_pc_sample;
+    ItmPacket_DataTracePcValue_Body data_trace_pc_value;
+    ItmPacket_DataTraceAddress_Body data_trace_address;
+    ItmPacket_DataTraceDataValue_Body data_trace_data_value;
+    ItmPacket_DataTraceMatch_Body data_trace_match;
+    ItmPacket_HardwareSource_Body hardware_source;
+    ItmPacket_ReservedHeader_Body reserved_header;
+    ItmPacket_MalformedPacket_Body malformed_packet;
+    ItmPacket_UnknownExtension_Body unknown_extension;
+    ItmPacket_Truncated_Body truncated;
+  };
+} ItmPacket;
+
+// Function called for every decoded packet (or decoding error)
+//
+// The `packet` pointer is only valid for the duration of the call. `context` is the pointer that
+// was passed to `itm_decoder_feed` / `itm_decoder_finish`
+typedef void (*ItmCallback)(const struct ItmPacket *packet, void *context);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Creates a new decoder
+//
+// The decoder must be destroyed with `itm_decoder_free`
+struct itm_decoder *itm_decoder_new(void);
+
+// Destroys a decoder created with `itm_decoder_new`
+//
+// # Safety
+//
+// `decoder` must be a pointer returned by `itm_decoder_new` that has not been freed yet, or NULL
+void itm_decoder_free(struct itm_decoder *decoder);
+
+// Feeds `len` bytes into the decoder and calls `callback` for every packet they complete
+//
+// # Safety
+//
+// `decoder` must be a valid decoder and `bytes` must point to at least `len` readable bytes
+void itm_decoder_feed(struct itm_decoder *decoder,
+                      const uint8_t *bytes,
+                      size_t len,
+                      ItmCallback callback,
+                      void *context);
+
+// Signals the end of the input; an incomplete packet held by the decoder is reported to
+// `callback` as a truncated packet
+//
+// # Safety
+//
+// `decoder` must be a valid decoder
+void itm_decoder_finish(struct itm_decoder *decoder, ItmCallback callback, void *context);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ITM_H */
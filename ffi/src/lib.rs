@@ -0,0 +1,558 @@
+//! C bindings for the `itm` crate
+//!
+//! The C header for this library is in `include/itm.h`. Usage:
+//!
+//! ``` c
+//! void on_packet(const ItmPacket *packet, void *context) {
+//!     if (packet->tag == ItmPacket_Instrumentation) {
+//!         fwrite(packet->instrumentation.payload, 1, packet->instrumentation.size, stdout);
+//!     }
+//! }
+//!
+//! itm_decoder *decoder = itm_decoder_new();
+//! itm_decoder_feed(decoder, bytes, len, on_packet, NULL);
+//! itm_decoder_finish(decoder, on_packet, NULL);
+//! itm_decoder_free(decoder);
+//! ```
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+use std::{os::raw::c_void, slice};
+
+use itm::{packet::Function, Decoder, Error, Packet};
+
+/// A push-based ITM packet decoder
+///
+/// This is an opaque type; create it with `itm_decoder_new` and destroy it with `itm_decoder_free`
+pub struct ItmDecoder {
+    decoder: Decoder,
+}
+
+/// Function called for every decoded packet (or decoding error)
+///
+/// The `packet` pointer is only valid for the duration of the call. `context` is the pointer that
+/// was passed to `itm_decoder_feed` / `itm_decoder_finish`
+pub type ItmCallback = Option<extern "C" fn(packet: *const ItmPacket, context: *mut c_void)>;
+
+/// The action taken by the processor
+#[cfg_attr(test, derive(Clone, Copy, Debug, PartialEq))]
+#[repr(C)]
+pub enum ItmFunction {
+    /// Entered exception
+    Enter,
+    /// Exited exception
+    Exit,
+    /// Returned to exception
+    Return,
+}
+
+/// A decoded ITM packet or a decoding error
+///
+/// See the documentation of the `itm` crate for the meaning of each field
+#[cfg_attr(test, derive(Clone, Copy, Debug, PartialEq))]
+#[repr(C, u8)]
+pub enum ItmPacket {
+    /// Overflow packet
+    Overflow,
+    /// Synchronization packet
+    Synchronization {
+        /// The length in bytes of the packet
+        len: u8,
+    },
+    /// Instrumentation packet
+    Instrumentation {
+        /// The stimulus port page of `port`
+        page: u8,
+        /// The stimulus port that generated this packet, within its page
+        port: u8,
+        /// The number of valid bytes in `payload` (1, 2 or 4)
+        size: u8,
+        /// The payload of this packet
+        payload: [u8; 4],
+    },
+    /// Local timestamp packet
+    LocalTimestamp {
+        /// The interval since the previous local timestamp packet
+        delta: u32,
+        /// The timestamp is delayed relative to the ITM or DWT data
+        timestamp_delayed: bool,
+        /// The ITM or DWT data is delayed relative to the associated event
+        event_delayed: bool,
+    },
+    /// Global timestamp packet (format 1)
+    Gts1 {
+        /// Timestamp bits (up to 26 bits)
+        bits: u32,
+        /// The clock change input has been asserted
+        clock_changed: bool,
+        /// The high-order bits of the global timestamp have changed
+        wrapped: bool,
+    },
+    /// Global timestamp packet (format 2)
+    Gts2 {
+        /// High-order bits of the global timestamp
+        bits: u64,
+        /// This is a 64-bit timestamp
+        is_64_bit: bool,
+    },
+    /// Stimulus Port Page (Extension packet)
+    StimulusPortPage {
+        /// Stimulus port page
+        page: u8,
+    },
+    /// Event Counter
+    EventCounter {
+        /// CPICNT wrapped around
+        cpi: bool,
+        /// EXCCNT wrapped around
+        exc: bool,
+        /// SLEEPCNT wrapped around
+        sleep: bool,
+        /// LSUCNT wrapped around
+        lsu: bool,
+        /// FOLDCNT wrapped around
+        fold: bool,
+        /// POSTCNT wrapped around
+        post: bool,
+    },
+    /// Exception Trace
+    ExceptionTrace {
+        /// Exception number
+        number: u16,
+        /// The action taken by the processor
+        function: ItmFunction,
+    },
+    /// Periodic PC Sample
+    PeriodicPcSample {
+        /// The core is sleeping; `pc` is not valid
+        sleeping: bool,
+        /// Sampled PC
+        pc: u32,
+    },
+    /// Data Trace PC Value
+    DataTracePcValue {
+        /// Comparator that generated the data
+        comparator: u8,
+        /// PC value of the instruction that caused the successful address comparison
+        pc: u32,
+    },
+    /// Data Trace Address
+    DataTraceAddress {
+        /// Comparator that generated the data
+        comparator: u8,
+        /// Data address that caused the successful address comparison
+        address: u16,
+    },
+    /// Data Trace Data Value
+    DataTraceDataValue {
+        /// Comparator that generated the data
+        comparator: u8,
+        /// This was a write access
+        write_access: bool,
+        /// The number of valid bytes in `value` (1, 2 or 4)
+        size: u8,
+        /// Data value that caused the successful data value comparison
+        value: [u8; 4],
+    },
+    /// Data Trace Match (ARMv8-M)
+    DataTraceMatch {
+        /// Comparator that generated the packet
+        comparator: u8,
+    },
+    /// Other hardware source packet
+    HardwareSource {
+        /// The discriminator, which identifies the type of the packet
+        discriminator: u8,
+        /// The number of valid bytes in `payload` (1, 2 or 4)
+        size: u8,
+        /// The payload of this packet
+        payload: [u8; 4],
+    },
+    /// Error: the packet starts with a reserved header byte
+    ReservedHeader {
+        /// The header byte
+        byte: u8,
+    },
+    /// Error: the packet doesn't adhere to the specification
+    MalformedPacket {
+        /// The header of the malformed packet
+        header: u8,
+        /// Length of the malformed packet in bytes, including the header
+        len: u8,
+    },
+    /// Error: the packet is an extension packet unknown to this library
+    UnknownExtension {
+        /// The header of the extension packet
+        header: u8,
+        /// Length of the extension packet in bytes, including the header
+        len: u8,
+    },
+    /// Error: the input ended in the middle of a packet
+    Truncated {
+        /// The header of the truncated packet
+        header: u8,
+        /// Number of bytes of the packet that were received, including the header
+        len: u8,
+    },
+    /// Error: a decoding error that has no representation in these bindings
+    OtherError,
+}
+
+impl From<Packet> for ItmPacket {
+    fn from(packet: Packet) -> ItmPacket {
+        match packet {
+            Packet::Overflow => ItmPacket::Overflow,
+            Packet::Synchronization(s) => ItmPacket::Synchronization { len: s.len() },
+            Packet::Instrumentation(i) => {
+                let (size, payload) = to_array(i.payload());
+                ItmPacket::Instrumentation {
+                    page: i.page(),
+                    port: i.port(),
+                    size,
+                    payload,
+                }
+            }
+            Packet::LocalTimestamp(lt) => ItmPacket::LocalTimestamp {
+                delta: lt.delta(),
+                timestamp_delayed: lt.timestamp_delayed(),
+                event_delayed: lt.event_delayed(),
+            },
+            Packet::GTS1(gt) => ItmPacket::Gts1 {
+                bits: gt.bits(),
+                clock_changed: gt.has_clock_changed(),
+                wrapped: gt.has_wrapped(),
+            },
+            Packet::GTS2(gt) => ItmPacket::Gts2 {
+                bits: gt.bits(),
+                is_64_bit: gt.is_64_bit(),
+            },
+            Packet::StimulusPortPage(spp) => ItmPacket::StimulusPortPage { page: spp.page() },
+            Packet::EventCounter(ec) => ItmPacket::EventCounter {
+                cpi: ec.cpi(),
+                exc: ec.exc(),
+                sleep: ec.sleep(),
+                lsu: ec.lsu(),
+                fold: ec.fold(),
+                post: ec.post(),
+            },
+            Packet::ExceptionTrace(et) => ItmPacket::ExceptionTrace {
+                number: et.number(),
+                function: match et.function() {
+                    Function::Enter => ItmFunction::Enter,
+                    Function::Exit => ItmFunction::Exit,
+                    Function::Return => ItmFunction::Return,
+                },
+            },
+            Packet::PeriodicPcSample(pps) => ItmPacket::PeriodicPcSample {
+                sleeping: pps.pc().is_none(),
+                pc: pps.pc().unwrap_or(0),
+            },
+            Packet::DataTracePcValue(dtpv) => ItmPacket::DataTracePcValue {
+                comparator: dtpv.comparator(),
+                pc: dtpv.pc(),
+            },
+            Packet::DataTraceAddress(dta) => ItmPacket::DataTraceAddress {
+                comparator: dta.comparator(),
+                address: dta.address(),
+            },
+            Packet::DataTraceDataValue(dtdv) => {
+                let (size, value) = to_array(dtdv.value());
+                ItmPacket::DataTraceDataValue {
+                    comparator: dtdv.comparator(),
+                    write_access: dtdv.write_access(),
+                    size,
+                    value,
+                }
+            }
+            Packet::DataTraceMatch(dtm) => ItmPacket::DataTraceMatch {
+                comparator: dtm.comparator(),
+            },
+            Packet::HardwareSource(hs) => {
+                let (size, payload) = to_array(hs.payload());
+                ItmPacket::HardwareSource {
+                    discriminator: hs.discriminator(),
+                    size,
+                    payload,
+                }
+            }
+        }
+    }
+}
+
+impl From<Error> for ItmPacket {
+    fn from(error: Error) -> ItmPacket {
+        match error {
+            Error::ReservedHeader { byte } => ItmPacket::ReservedHeader { byte },
+            Error::MalformedPacket { header, len } => ItmPacket::MalformedPacket { header, len },
+            Error::UnknownExtension { header, len } => ItmPacket::UnknownExtension { header, len },
+            Error::Truncated { header, len } => ItmPacket::Truncated { header, len },
+            // `Error` is non-exhaustive; panicking here would abort the host process
+            _ => ItmPacket::OtherError,
+        }
+    }
+}
+
+fn to_array(bytes: &[u8]) -> (u8, [u8; 4]) {
+    let mut array = [0; 4];
+    array[..bytes.len()].copy_from_slice(bytes);
+    (bytes.len() as u8, array)
+}
+
+fn call(res: Result<Packet, Error>, callback: ItmCallback, context: *mut c_void) {
+    let packet = match res {
+        Ok(packet) => ItmPacket::from(packet),
+        Err(e) => ItmPacket::from(e),
+    };
+
+    if let Some(callback) = callback {
+        callback(&packet, context);
+    }
+}
+
+/// Creates a new decoder
+///
+/// The decoder must be destroyed with `itm_decoder_free`
+#[no_mangle]
+pub extern "C" fn itm_decoder_new() -> *mut ItmDecoder {
+    Box::into_raw(Box::new(ItmDecoder {
+        decoder: Decoder::new(),
+    }))
+}
+
+/// Destroys a decoder created with `itm_decoder_new`
+///
+/// # Safety
+///
+/// `decoder` must be a pointer returned by `itm_decoder_new` that has not been freed yet, or NULL
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_free(decoder: *mut ItmDecoder) {
+    if !decoder.is_null() {
+        drop(Box::from_raw(decoder));
+    }
+}
+
+/// Feeds `len` bytes into the decoder and calls `callback` for every packet they complete
+///
+/// # Safety
+///
+/// `decoder` must be a valid decoder and `bytes` must point to at least `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_feed(
+    decoder: *mut ItmDecoder,
+    bytes: *const u8,
+    len: usize,
+    callback: ItmCallback,
+    context: *mut c_void,
+) {
+    let decoder = &mut (*decoder).decoder;
+
+    if len != 0 {
+        decoder.feed(slice::from_raw_parts(bytes, len));
+    }
+
+    for res in decoder {
+        call(res, callback, context);
+    }
+}
+
+/// Signals the end of the input; an incomplete packet held by the decoder is reported to
+/// `callback` as a truncated packet
+///
+/// # Safety
+///
+/// `decoder` must be a valid decoder
+#[no_mangle]
+pub unsafe extern "C" fn itm_decoder_finish(
+    decoder: *mut ItmDecoder,
+    callback: ItmCallback,
+    context: *mut c_void,
+) {
+    if let Some(e) = (*decoder).decoder.finish() {
+        call(Err(e), callback, context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, os::raw::c_void, ptr};
+
+    use itm::{
+        packet::{
+            DataTraceDataValue, ExceptionTrace, Function, HardwareSource, Instrumentation,
+            PeriodicPcSample, Synchronization,
+        },
+        Error, Packet,
+    };
+
+    use crate::{
+        itm_decoder_feed, itm_decoder_finish, itm_decoder_free, itm_decoder_new, to_array,
+        ItmFunction, ItmPacket,
+    };
+
+    // collects the packets into the `Vec<ItmPacket>` pointed to by `context`
+    extern "C" fn collect(packet: *const ItmPacket, context: *mut c_void) {
+        unsafe {
+            (*(context as *mut Vec<ItmPacket>)).push(*packet);
+        }
+    }
+
+    #[test]
+    fn feed_finish() {
+        let mut out: Vec<ItmPacket> = vec![];
+        let context = &mut out as *mut Vec<ItmPacket> as *mut c_void;
+
+        unsafe {
+            let decoder = itm_decoder_new();
+
+            // an instrumentation packet, split across two calls, and the start of another one
+            let bytes = [0x01, b'a', 0x03, b'b'];
+            itm_decoder_feed(decoder, bytes.as_ptr(), 1, Some(collect), context);
+            assert!(out.is_empty());
+            itm_decoder_feed(decoder, bytes[1..].as_ptr(), 3, Some(collect), context);
+            assert_eq!(out.len(), 1);
+
+            itm_decoder_finish(decoder, Some(collect), context);
+            itm_decoder_free(decoder);
+        }
+
+        assert_eq!(
+            out,
+            [
+                ItmPacket::Instrumentation {
+                    page: 0,
+                    port: 0,
+                    size: 1,
+                    payload: [b'a', 0, 0, 0],
+                },
+                ItmPacket::Truncated {
+                    header: 0x03,
+                    len: 2
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn null_callback() {
+        unsafe {
+            let decoder = itm_decoder_new();
+
+            // packets are decoded and dropped
+            let bytes = [0x01, b'a', 0x03, b'b'];
+            itm_decoder_feed(decoder, bytes.as_ptr(), bytes.len(), None, ptr::null_mut());
+            // no bytes; the pointer is not read
+            itm_decoder_feed(decoder, ptr::null(), 0, None, ptr::null_mut());
+            itm_decoder_finish(decoder, None, ptr::null_mut());
+
+            itm_decoder_free(decoder);
+            itm_decoder_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn from_packet() {
+        assert_eq!(ItmPacket::from(Packet::Overflow), ItmPacket::Overflow);
+        assert_eq!(
+            ItmPacket::from(Packet::Synchronization(Synchronization::new(6))),
+            ItmPacket::Synchronization { len: 6 }
+        );
+        assert_eq!(
+            ItmPacket::from(Packet::Instrumentation(Instrumentation::new(2, b"ab"))),
+            ItmPacket::Instrumentation {
+                page: 0,
+                port: 2,
+                size: 2,
+                payload: [b'a', b'b', 0, 0],
+            }
+        );
+        assert_eq!(
+            ItmPacket::from(Packet::ExceptionTrace(ExceptionTrace::new(
+                15,
+                Function::Return
+            ))),
+            ItmPacket::ExceptionTrace {
+                number: 15,
+                function: ItmFunction::Return,
+            }
+        );
+        assert_eq!(
+            ItmPacket::from(Packet::PeriodicPcSample(PeriodicPcSample::new(None))),
+            ItmPacket::PeriodicPcSample {
+                sleeping: true,
+                pc: 0,
+            }
+        );
+        assert_eq!(
+            ItmPacket::from(Packet::DataTraceDataValue(DataTraceDataValue::new(
+                1,
+                true,
+                &[1, 2, 3, 4]
+            ))),
+            ItmPacket::DataTraceDataValue {
+                comparator: 1,
+                write_access: true,
+                size: 4,
+                value: [1, 2, 3, 4],
+            }
+        );
+        assert_eq!(
+            ItmPacket::from(Packet::HardwareSource(HardwareSource::new(31, &[0xff]))),
+            ItmPacket::HardwareSource {
+                discriminator: 31,
+                size: 1,
+                payload: [0xff, 0, 0, 0],
+            }
+        );
+    }
+
+    #[test]
+    fn from_error() {
+        assert_eq!(
+            ItmPacket::from(Error::ReservedHeader { byte: 0x04 }),
+            ItmPacket::ReservedHeader { byte: 0x04 }
+        );
+        assert_eq!(
+            ItmPacket::from(Error::MalformedPacket {
+                header: 0x70,
+                len: 2
+            }),
+            ItmPacket::MalformedPacket {
+                header: 0x70,
+                len: 2
+            }
+        );
+        assert_eq!(
+            ItmPacket::from(Error::UnknownExtension {
+                header: 0x0c,
+                len: 1
+            }),
+            ItmPacket::UnknownExtension {
+                header: 0x0c,
+                len: 1
+            }
+        );
+        assert_eq!(
+            ItmPacket::from(Error::Truncated {
+                header: 0x03,
+                len: 3
+            }),
+            ItmPacket::Truncated {
+                header: 0x03,
+                len: 3
+            }
+        );
+        // no C representation
+        assert_eq!(
+            ItmPacket::from(Error::Io(io::ErrorKind::Other.into())),
+            ItmPacket::OtherError
+        );
+    }
+
+    #[test]
+    fn to_array_sizes() {
+        assert_eq!(to_array(&[1]), (1, [1, 0, 0, 0]));
+        assert_eq!(to_array(&[1, 2]), (2, [1, 2, 0, 0]));
+        assert_eq!(to_array(&[1, 2, 3, 4]), (4, [1, 2, 3, 4]));
+    }
+}
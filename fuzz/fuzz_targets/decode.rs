@@ -0,0 +1,55 @@
+//! Decoding arbitrary bytes never panics, whether they arrive in one piece or in chunks
+
+#![no_main]
+
+use itm::{Decoder, SliceDecoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte picks the chunk size used to feed the `Decoder`
+    let (chunk, bytes) = match data.split_first() {
+        Some((&chunk, bytes)) => (usize::from(chunk).max(1), bytes),
+        None => return,
+    };
+
+    let mut decoder = Decoder::new();
+    let mut len = 0;
+    let mut items = vec![];
+    for chunk in bytes.chunks(chunk) {
+        decoder.feed(chunk);
+        for item in &mut decoder {
+            len += match &item {
+                Ok(packet) => usize::from(packet.len()),
+                Err(e) => usize::from(e.len()),
+            };
+            items.push(item.ok());
+        }
+    }
+    if let Some(e) = decoder.finish() {
+        len += usize::from(e.len());
+    }
+
+    // every input byte is accounted for
+    assert_eq!(len, bytes.len());
+    assert_eq!(decoder.stats().bytes(), bytes.len() as u64);
+
+    // `SliceDecoder` agrees with `Decoder`
+    let mut slice_decoder = SliceDecoder::new(bytes);
+    let slice_items = (&mut slice_decoder).map(Result::ok).collect::<Vec<_>>();
+    assert_eq!(slice_items, items);
+
+    // every decoded packet encodes back into the bytes it was decoded from
+    let mut offset = 0;
+    for item in SliceDecoder::new(bytes) {
+        let len = match item {
+            Ok(packet) => {
+                let len = usize::from(packet.len());
+                assert_eq!(packet.to_bytes(), &bytes[offset..offset + len]);
+                len
+            }
+            Err(e) => usize::from(e.len()),
+        };
+        offset += len;
+    }
+    assert_eq!(bytes.len() - offset, slice_decoder.remainder().len());
+});
@@ -0,0 +1,30 @@
+//! Encoding a packet and decoding the result gives back the same packet
+
+#![no_main]
+
+use itm::{Packet, SliceDecoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|packets: Vec<Packet>| {
+    let mut packets = packets;
+
+    // instrumentation packets are decoded on the page selected by the preceding page packets
+    let mut page = 0;
+    for packet in &mut packets {
+        match packet {
+            Packet::StimulusPortPage(spp) => page = spp.page(),
+            Packet::Instrumentation(i) => *i = i.with_page(page),
+            _ => {}
+        }
+    }
+
+    let bytes = packets
+        .iter()
+        .flat_map(|packet| packet.to_bytes())
+        .collect::<Vec<_>>();
+
+    let decoded = SliceDecoder::new(&bytes)
+        .map(Result::unwrap)
+        .collect::<Vec<_>>();
+    assert_eq!(decoded, packets);
+});
@@ -0,0 +1,151 @@
+//! `arbitrary::Arbitrary` implementations for the packet types
+//!
+//! Packets are built using their constructors so only packets that can appear in an ITM stream are
+//! generated
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::packet::{
+    DataTraceAddress, DataTraceDataValue, DataTraceMatch, DataTracePcValue, EventCounter,
+    Exception, ExceptionTrace, HardwareSource, Instrumentation, LocalTimestamp, PeriodicPcSample,
+    StimulusPortPage, Synchronization, GTS1, GTS2,
+};
+
+// a 1, 2 or 4 byte payload
+fn payload(u: &mut Unstructured) -> Result<([u8; 4], usize)> {
+    let len = *u.choose(&[1, 2, 4])?;
+    let bytes = <[u8; 4]>::arbitrary(u)?;
+    Ok((bytes, len))
+}
+
+impl<'a> Arbitrary<'a> for Synchronization {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Synchronization::new(u.int_in_range(6..=u8::MAX)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Instrumentation {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let port = u.int_in_range(0..=31)?;
+        let (bytes, len) = payload(u)?;
+        Ok(Instrumentation::new(port, &bytes[..len]))
+    }
+}
+
+impl<'a> Arbitrary<'a> for LocalTimestamp {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(LocalTimestamp::new(
+            u.int_in_range(0..=(1 << 28) - 1)?,
+            bool::arbitrary(u)?,
+            bool::arbitrary(u)?,
+        ))
+    }
+}
+
+impl<'a> Arbitrary<'a> for GTS1 {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(GTS1::new(
+            u.int_in_range(0..=(1 << 26) - 1)?,
+            bool::arbitrary(u)?,
+            bool::arbitrary(u)?,
+        ))
+    }
+}
+
+impl<'a> Arbitrary<'a> for GTS2 {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let is_64_bit = bool::arbitrary(u)?;
+        let width = if is_64_bit { 38 } else { 22 };
+        Ok(GTS2::new(u.int_in_range(0..=(1 << width) - 1)?, is_64_bit))
+    }
+}
+
+impl<'a> Arbitrary<'a> for StimulusPortPage {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(StimulusPortPage::new(u.int_in_range(0..=7)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for EventCounter {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(EventCounter::new(
+            bool::arbitrary(u)?,
+            bool::arbitrary(u)?,
+            bool::arbitrary(u)?,
+            bool::arbitrary(u)?,
+            bool::arbitrary(u)?,
+            bool::arbitrary(u)?,
+        ))
+    }
+}
+
+impl<'a> Arbitrary<'a> for ExceptionTrace {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(ExceptionTrace::new(
+            u.int_in_range(0..=(1 << 9) - 1)?,
+            Arbitrary::arbitrary(u)?,
+        ))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Exception {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Exception::from_number(u.int_in_range(0..=(1 << 9) - 1)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for PeriodicPcSample {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(PeriodicPcSample::new(Arbitrary::arbitrary(u)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for DataTracePcValue {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(DataTracePcValue::new(
+            u.int_in_range(0..=3)?,
+            u32::arbitrary(u)?,
+        ))
+    }
+}
+
+impl<'a> Arbitrary<'a> for DataTraceAddress {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(DataTraceAddress::new(
+            u.int_in_range(0..=3)?,
+            u16::arbitrary(u)?,
+        ))
+    }
+}
+
+impl<'a> Arbitrary<'a> for DataTraceDataValue {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let comparator = u.int_in_range(0..=3)?;
+        let write_access = bool::arbitrary(u)?;
+        let (bytes, len) = payload(u)?;
+        Ok(DataTraceDataValue::new(
+            comparator,
+            write_access,
+            &bytes[..len],
+        ))
+    }
+}
+
+impl<'a> Arbitrary<'a> for DataTraceMatch {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(DataTraceMatch::new(u.int_in_range(0..=3)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for HardwareSource {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let (bytes, len) = payload(u)?;
+        let discriminators = (0..32)
+            .filter(|&disc| HardwareSource::is_generic(disc, len as u8))
+            .collect::<Vec<_>>();
+        Ok(HardwareSource::new(
+            *u.choose(&discriminators)?,
+            &bytes[..len],
+        ))
+    }
+}
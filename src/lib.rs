@@ -3,52 +3,106 @@
 //! # References
 //!
 //! - [ARMv7-M Architecture Reference Manual (DDI 0403E.b)][0] - Appendix D4 Debug ITM and DWT
-//! Packet Protocol
+//!   Packet Protocol
 //!
 //! [0]: https://static.docs.arm.com/ddi0403/eb/DDI0403E_B_armv7m_arm.pdf
 //!
 //! - [CoreSight Components Technical Reference Manual (DDI 0314H)][1] - Chapter 12 Instrumentation
-//! Trace Macrocell
+//!   Trace Macrocell
 //!
 //! [1]: http://infocenter.arm.com/help/topic/com.arm.doc.ddi0314h/DDI0314H_coresight_components_trm.pdf
+//!
+//! # Cargo features
+//!
+//! - `std` (enabled by default). Enables the `Stream`, `Decoder` and `timeline` APIs. Without this
+//!   feature the crate is `no_std` and allocation free; packets can still be decoded using
+//!   `SliceDecoder`.
+//! - `tokio`. Enables `AsyncStream`, which reads packets from a `tokio::io::AsyncRead` source.
+//! - `arbitrary`. Implements `arbitrary::Arbitrary` for `Packet` and the types in the `packet`
+//!   module, for fuzzing. Only packets that can appear in an ITM stream are generated. Implies
+//!   `std`.
+//! - `test-util`. Enables the `test_util` module: `proptest` strategies that generate ITM packets
+//!   and streams.
+//! - `log`. Logs diagnostics through the `log` crate (see below).
+//! - `serde`. Implements `Serialize` and `Deserialize` for `Packet` and the types in the `packet`
+//!   module.
+//!
+//! # Logging
+//!
+//! With the `log` feature enabled the `Decoder` and the streams log under these targets:
+//!
+//! - `itm::decode`. Decoded packets (`trace` level) and decoding errors (`debug` level), with
+//!   their input offset (see `Decoder::offset`).
+//! - `itm::resync`. The decoder discarding its state to start over, or skipping input while it
+//!   waits for a synchronization packet (`debug` level).
+//! - `itm::io`. Reads (`trace` level), EOF and I/O errors (`debug` level) of `Stream` and
+//!   `AsyncStream`.
+//!
+//! For example, `RUST_LOG=itm::decode=debug` reports where decoding errors are in the input when
+//! using `env_logger`.
 
+#![cfg_attr(not(feature = "std"), no_std)]
 #![deny(missing_docs)]
 #![deny(warnings)]
 
-use core::fmt;
+#[cfg(feature = "std")]
+use core::{convert::TryFrom, fmt};
+#[cfg(feature = "std")]
 use std::io::{self, ErrorKind, Read};
 
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
 use byteorder::{ByteOrder, LE};
 use either::Either;
 use thiserror::Error;
 
 use crate::packet::{
-    DataTraceAddress, DataTraceDataValue, DataTracePcValue, EventCounter, ExceptionTrace, Function,
-    Instrumentation, LocalTimestamp, PeriodicPcSample, StimulusPortPage, Synchronization, GTS1,
-    GTS2,
+    DataTraceAddress, DataTraceDataValue, DataTraceMatch, DataTracePcValue, EventCounter,
+    ExceptionTrace, Function, HardwareSource, Instrumentation, Kind, LocalTimestamp, Payload,
+    PeriodicPcSample, Source, StimulusPortPage, Synchronization, GTS1, GTS2,
 };
+#[cfg(feature = "std")]
+use crate::tpiu::{Deframer, Format};
+
+// forwards to the `log` crate when the `log` feature is enabled; does nothing otherwise
+#[cfg(feature = "std")]
+macro_rules! log {
+    ($level:ident, $target:expr, $($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::$level!(target: $target, $($arg)+);
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)+);
+    }};
+}
 
+#[cfg(feature = "arbitrary")]
+mod arbitrary_impls;
 pub mod packet;
-#[cfg(test)]
+pub mod swo;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(all(test, feature = "std"))]
 mod tests;
+#[cfg(feature = "std")]
+pub mod timeline;
+pub mod tpiu;
 
 /// A stream of ITM packets
+#[cfg(feature = "std")]
 pub struct Stream<R>
 where
     R: Read,
 {
     // have we reached the EOF of the reader?
     at_eof: bool,
-    // NOTE size is optimized for reading from `/dev/ttyUSB*`; `Read::read` usually reads in 32-byte
-    // chunks
-    buffer: [u8; 64],
+    decoder: Decoder,
     // whether to continue reading past a (temporary) EOF condition
     keep_reading: bool,
-    // number of read bytes in `buffer`
-    len: usize,
     reader: R,
 }
 
+#[cfg(feature = "std")]
 impl<R> fmt::Debug for Stream<R>
 where
     R: fmt::Debug + Read,
@@ -56,13 +110,14 @@ where
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Stream")
             .field("at_eof", &self.at_eof)
-            .field("buffer", &&self.buffer[..self.len])
+            .field("decoder", &self.decoder)
             .field("keep_reading", &self.keep_reading)
             .field("reader", &self.reader)
             .finish()
     }
 }
 
+#[cfg(feature = "std")]
 impl<R> Stream<R>
 where
     R: Read,
@@ -72,11 +127,15 @@ where
     /// If `keep_reading` is set to `true` the stream will continue to read to `Reader` object past
     /// (temporary) EOF conditions
     pub fn new(reader: R, keep_reading: bool) -> Stream<R> {
+        Stream::with_decoder(Decoder::new(), reader, keep_reading)
+    }
+
+    /// Like `new` but packets are decoded using the given `decoder`
+    pub fn with_decoder(decoder: Decoder, reader: R, keep_reading: bool) -> Stream<R> {
         Stream {
-            buffer: [0; 64],
             at_eof: false,
+            decoder,
             keep_reading,
-            len: 0,
             reader,
         }
     }
@@ -89,62 +148,246 @@ where
     /// set to `false` (see constructor)
     ///
     /// `Ok(Some(..))` is the result of parsing the stream data into an ITM packet
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> io::Result<Option<Result<Packet, Error>>> {
         if self.at_eof {
             return Ok(None);
         }
 
-        'extract: loop {
-            match parse(&self.buffer[..self.len]) {
-                Ok(packet) => {
-                    self.rotate_left(usize::from(packet.len()));
+        loop {
+            if let Some(res) = self.decoder.next() {
+                return Ok(Some(res));
+            }
+
+            // need more bytes
+            // NOTE size is optimized for reading from `/dev/ttyUSB*`; `Read::read` usually reads in
+            // 32-byte chunks
+            let mut buffer = [0; 64];
+            match self.reader.read(&mut buffer) {
+                Ok(0) => {
+                    if self.keep_reading {
+                        continue;
+                    } else {
+                        // reached EOF
+                        log!(debug, "itm::io", "EOF");
 
-                    return Ok(Some(Ok(packet)));
+                        return Ok(self.decoder.finish().map(|e| {
+                            // truncated packet
+                            self.at_eof = true;
+                            Err(e)
+                        }));
+                    }
                 }
-                // parsing error
-                Err(Either::Left(e)) => {
-                    // skip malformed packet
-                    self.rotate_left(usize::from(e.len()));
+                // got more data; try to extract a packet again
+                Ok(len) => {
+                    log!(trace, "itm::io", "read {} bytes", len);
 
-                    return Ok(Some(Err(e)));
+                    self.decoder.feed(&buffer[..len])
                 }
-                Err(Either::Right(NeedMoreBytes)) => {
-                    // need more bytes
-                    'read: loop {
-                        match self.reader.read(&mut self.buffer[self.len..]) {
-                            Ok(0) => {
-                                if self.keep_reading {
-                                    continue 'read;
-                                } else {
-                                    // reached EOF
-                                    if self.len == 0 {
-                                        return Ok(None);
-                                    } else {
-                                        // truncated packet
-                                        self.at_eof = true;
-                                        return Ok(Some(Err(Error::MalformedPacket {
-                                            header: self.buffer[0],
-                                            len: self.len as u8,
-                                        })));
-                                    }
-                                }
-                            }
-                            Ok(len) => {
-                                self.len += len;
-                                // got more data; try to extract a packet again
-                                continue 'extract;
-                            }
-                            Err(e) => match e.kind() {
-                                ErrorKind::Interrupted => continue 'read,
-                                _ => return Err(e),
-                            },
-                        }
+                Err(e) => match e.kind() {
+                    ErrorKind::Interrupted => continue,
+                    _ => {
+                        log!(debug, "itm::io", "read error: {}", e);
+
+                        return Err(e);
                     }
+                },
+            }
+        }
+    }
+
+    /// Gets a reference to the decoder used by this stream.
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Gets a mutable reference to the decoder used by this stream.
+    ///
+    /// To resume decoding after the source has been reopened (e.g. a network connection or a
+    /// serial device that dropped), replace the reader using `get_mut` and `reset` the decoder so
+    /// the bytes of the old source that were not yet decoded are discarded.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+
+    /// Gets a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Gets a mutable reference to the underlying reader.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R> IntoIterator for Stream<R>
+where
+    R: Read,
+{
+    type Item = Result<Packet, Error>;
+    type IntoIter = Packets<R>;
+
+    fn into_iter(self) -> Packets<R> {
+        Packets { stream: self }
+    }
+}
+
+/// An iterator over the ITM packets of a `Stream`
+///
+/// I/O errors are reported as `Error::Io`. The iterator ends once `Stream::next` returns `Ok(None)`
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct Packets<R>
+where
+    R: Read,
+{
+    stream: Stream<R>,
+}
+
+#[cfg(feature = "std")]
+impl<R> Packets<R>
+where
+    R: Read,
+{
+    /// Gets a reference to the underlying stream.
+    pub fn get_ref(&self) -> &Stream<R> {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut Stream<R> {
+        &mut self.stream
+    }
+
+    /// Unwraps this iterator, returning the underlying stream.
+    pub fn into_inner(self) -> Stream<R> {
+        self.stream
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R> Iterator for Packets<R>
+where
+    R: Read,
+{
+    type Item = Result<Packet, Error>;
+
+    fn next(&mut self) -> Option<Result<Packet, Error>> {
+        match self.stream.next() {
+            Ok(res) => res,
+            Err(e) => Some(Err(Error::Io(e))),
+        }
+    }
+}
+
+/// An asynchronous stream of ITM packets
+///
+/// This is the `async` version of `Stream`
+#[cfg(feature = "tokio")]
+pub struct AsyncStream<R>
+where
+    R: AsyncRead + Unpin,
+{
+    // have we reached the EOF of the reader?
+    at_eof: bool,
+    decoder: Decoder,
+    reader: R,
+}
+
+#[cfg(feature = "tokio")]
+impl<R> fmt::Debug for AsyncStream<R>
+where
+    R: fmt::Debug + AsyncRead + Unpin,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AsyncStream")
+            .field("at_eof", &self.at_eof)
+            .field("decoder", &self.decoder)
+            .field("reader", &self.reader)
+            .finish()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<R> AsyncStream<R>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Creates a stream of ITM packets from the given `AsyncRead` object
+    pub fn new(reader: R) -> AsyncStream<R> {
+        AsyncStream::with_decoder(Decoder::new(), reader)
+    }
+
+    /// Like `new` but packets are decoded using the given `decoder`
+    pub fn with_decoder(decoder: Decoder, reader: R) -> AsyncStream<R> {
+        AsyncStream {
+            at_eof: false,
+            decoder,
+            reader,
+        }
+    }
+
+    /// Returns the next packet in this stream
+    ///
+    /// See `Stream::next` for the meaning of the return value. `Ok(None)` is returned once the
+    /// reader reaches EOF
+    pub async fn next(&mut self) -> io::Result<Option<Result<Packet, Error>>> {
+        if self.at_eof {
+            return Ok(None);
+        }
+
+        loop {
+            if let Some(res) = self.decoder.next() {
+                return Ok(Some(res));
+            }
+
+            // need more bytes
+            let mut buffer = [0; 64];
+            match self.reader.read(&mut buffer).await {
+                // reached EOF
+                Ok(0) => {
+                    log!(debug, "itm::io", "EOF");
+
+                    return Ok(self.decoder.finish().map(|e| {
+                        // truncated packet
+                        self.at_eof = true;
+                        Err(e)
+                    }));
+                }
+                // got more data; try to extract a packet again
+                Ok(len) => {
+                    log!(trace, "itm::io", "read {} bytes", len);
+
+                    self.decoder.feed(&buffer[..len])
                 }
+                Err(e) => match e.kind() {
+                    ErrorKind::Interrupted => continue,
+                    _ => {
+                        log!(debug, "itm::io", "read error: {}", e);
+
+                        return Err(e);
+                    }
+                },
             }
         }
     }
 
+    /// Gets a reference to the decoder used by this stream.
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Gets a mutable reference to the decoder used by this stream.
+    ///
+    /// To resume decoding after the source has been reopened (e.g. a network connection or a
+    /// serial device that dropped), replace the reader using `get_mut` and `reset` the decoder so
+    /// the bytes of the old source that were not yet decoded are discarded.
+    pub fn decoder_mut(&mut self) -> &mut Decoder {
+        &mut self.decoder
+    }
+
     /// Gets a reference to the underlying reader.
     pub fn get_ref(&self) -> &R {
         &self.reader
@@ -154,21 +397,812 @@ where
     pub fn get_mut(&mut self) -> &mut R {
         &mut self.reader
     }
+}
+
+/// A push-based ITM packet decoder
+///
+/// Bytes are pushed into the decoder using the `feed` method, in chunks of arbitrary size, and
+/// complete packets are pulled out of it using its `Iterator` implementation. Bytes that belong to
+/// a packet that has not been fully received yet are kept inside the decoder until the rest of the
+/// packet is fed.
+///
+/// `Decoder` is `Send` and `Sync` so, for example, a capture thread can hand a decoder, partially
+/// received packet included, over to a processing thread.
+#[cfg(feature = "std")]
+#[derive(Clone, Default)]
+pub struct Decoder {
+    // stopped by `HeaderPolicy::Abort`
+    aborted: bool,
+    buffer: Vec<u8>,
+    config: Config,
+    // demultiplexes the input when it's TPIU framed
+    deframer: Deframer,
+    // length of the last yielded packet / error; its bytes are right before `buffer[start]`
+    last_len: usize,
+    // input offset of the last yielded packet / error
+    offset: u64,
+    // stimulus port page selected by the last Stimulus Port Page packet
+    page: u8,
+    // input offset of `buffer[start]`
+    position: u64,
+    // one bit per recently yielded item, newest in the LSB; set for errors
+    recent_errors: u16,
+    // index of the first byte in `buffer` that has not been decoded yet
+    start: usize,
+    stats: Stats,
+    // discarding the input until the next synchronization packet
+    waiting: bool,
+}
+
+#[cfg(feature = "std")]
+impl fmt::Debug for Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Decoder")
+            .field("aborted", &self.aborted)
+            .field("buffer", &&self.buffer[self.start..])
+            .field("config", &self.config)
+            .field("deframer", &self.deframer)
+            .field("page", &self.page)
+            .field("position", &self.position)
+            .field("stats", &self.stats)
+            .field("recent_errors", &self.recent_errors)
+            .field("waiting", &self.waiting)
+            .finish()
+    }
+}
+
+// `Decoder` and the values it produces can be moved to, and shared with, other threads; this fails
+// to compile if a change breaks that guarantee
+#[cfg(feature = "std")]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Decoder>();
+    assert_send_sync::<DecoderBuilder>();
+    assert_send_sync::<Error>();
+    assert_send_sync::<Packet>();
+    assert_send_sync::<SliceDecoder<'static>>();
+    assert_send_sync::<Stats>();
+};
 
-    // like `slice.rotate_left` but doesn't touch the unused parts of the buffer
-    fn rotate_left(&mut self, shift: usize) {
-        for i in 0..self.len - shift {
-            self.buffer[i] = self.buffer[i + shift];
+#[cfg(feature = "std")]
+impl Decoder {
+    /// Creates a new decoder that has not been fed any byte
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Returns a builder to create a decoder with non-default settings
+    pub fn builder() -> DecoderBuilder {
+        DecoderBuilder::new()
+    }
+
+    /// Pushes more bytes into the decoder
+    ///
+    /// Use the `Iterator` implementation to extract the packets that these bytes complete
+    pub fn feed(&mut self, bytes: &[u8]) {
+        // discard the bytes that have already been decoded, except for the ones of the last
+        // yielded packet
+        self.buffer.drain(..self.start - self.last_len);
+        self.start = self.last_len;
+
+        match self.config.framing {
+            Format::Raw => self.buffer.extend_from_slice(bytes),
+            Format::Tpiu => {
+                let buffer = &mut self.buffer;
+                self.deframer.feed(bytes, |id, data| {
+                    if id == ITM_ID {
+                        buffer.extend_from_slice(data);
+                    }
+                });
+            }
+        }
+    }
+
+    /// Signals that no more bytes will be fed into the decoder
+    ///
+    /// If the decoder is holding the bytes of an incomplete packet those bytes are discarded and
+    /// reported as an `Error::Truncated` packet
+    pub fn finish(&mut self) -> Option<Error> {
+        if self.aborted {
+            // the pending bytes were not decoded, they are not a truncated packet
+            return None;
+        }
+
+        let pending = &self.buffer[self.start..];
+        let pending_len = pending.len();
+
+        // NOTE an incomplete packet is shorter than the longest (synchronization) packet, whose
+        // length fits in a `u8`, so this doesn't saturate
+        let error = pending.first().map(|&header| Error::Truncated {
+            header,
+            len: u8::try_from(pending_len).unwrap_or(u8::MAX),
+        });
+
+        if let Some(e) = &error {
+            self.stats.record_error(e);
+            self.offset = self.position;
+            self.position += pending_len as u64;
+
+            log!(debug, "itm::decode", "offset={} {}", self.offset, e);
+
+            // the truncated packet becomes the last yielded item
+            self.buffer.drain(..self.start);
+            self.start = self.buffer.len();
+            self.last_len = self.start;
+        }
+
+        error
+    }
+
+    /// Returns the bytes that have been fed into the decoder but not decoded yet
+    ///
+    /// Once the `Iterator` implementation has returned `None` these are the first bytes of an
+    /// incomplete packet; an empty slice means that the decoder is not in the middle of a packet.
+    pub fn pending_bytes(&self) -> &[u8] {
+        &self.buffer[self.start..]
+    }
+
+    /// Returns the exact input bytes, header included, of the last packet or error yielded by
+    /// this decoder
+    ///
+    /// For `Error::Truncated` errors returned by `finish` these are the bytes that were received.
+    /// Like `offset`, this also tracks the packets that are not yielded because their kind is
+    /// ignored.
+    ///
+    /// ```
+    /// use itm::Decoder;
+    ///
+    /// let mut decoder = Decoder::new();
+    /// decoder.feed(&[0x70, 0x01, b'A']);
+    ///
+    /// decoder.next();
+    /// assert_eq!(decoder.raw_bytes(), &[0x70]);
+    /// decoder.next();
+    /// assert_eq!(decoder.raw_bytes(), &[0x01, b'A']);
+    /// ```
+    pub fn raw_bytes(&self) -> &[u8] {
+        &self.buffer[self.start - self.last_len..self.start]
+    }
+
+    /// Returns the decoder to the state it had when it was built
+    ///
+    /// Pending bytes are discarded, without being reported, and the statistics and offsets are
+    /// cleared; the settings are kept. Use this when the input restarts from an arbitrary byte,
+    /// e.g. after the debug probe has been reconnected.
+    pub fn reset(&mut self) {
+        log!(
+            debug,
+            "itm::resync",
+            "offset={} reset; discarding {} pending bytes",
+            self.position,
+            self.pending_bytes().len()
+        );
+
+        *self = DecoderBuilder {
+            config: self.config.clone(),
         }
+        .build();
+    }
 
-        self.len -= shift;
+    /// Returns the input offset, in bytes, of the last packet or error yielded by this decoder
+    ///
+    /// The offset is counted from the first byte fed into the decoder. Together with `Packet::len`
+    /// and `Error::len` this locates the item in the input
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the statistics of all the input decoded so far
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
+
+/// Returns `None` when more bytes need to be fed into the decoder to complete the next packet
+#[cfg(feature = "std")]
+impl Iterator for Decoder {
+    type Item = Result<Packet, Error>;
+
+    fn next(&mut self) -> Option<Result<Packet, Error>> {
+        if self.aborted {
+            return None;
+        }
+
+        loop {
+            if self.waiting && !self.skip_to_sync() {
+                return None;
+            }
+
+            let res = match parse(&self.buffer[self.start..]) {
+                // this encoding is reserved in ARMv7-M
+                Ok(packet)
+                    if self.config.architecture == Architecture::ARMv7M
+                        && packet.kind() == Kind::DataTraceMatch =>
+                {
+                    let e = Error::MalformedPacket {
+                        header: self.buffer[self.start],
+                        len: packet.len(),
+                    };
+                    self.stats.record_error(&e);
+
+                    Err(e)
+                }
+                // not defined by the specification
+                Ok(packet) if self.config.strict && packet.kind() == Kind::HardwareSource => {
+                    let e = Error::MalformedPacket {
+                        header: self.buffer[self.start],
+                        len: packet.len(),
+                    };
+                    self.stats.record_error(&e);
+
+                    Err(e)
+                }
+                Ok(mut packet) => {
+                    track_page(&mut self.page, &mut packet);
+                    self.stats.record_packet(&packet);
+
+                    Ok(packet)
+                }
+                // parsing error
+                Err(Either::Left(e)) => {
+                    self.stats.record_error(&e);
+
+                    Err(e)
+                }
+                Err(Either::Right(NeedMoreBytes)) => return None,
+            };
+
+            // skip the packet, or the malformed packet
+            let len = match &res {
+                Ok(packet) => packet.len(),
+                Err(e) => e.len(),
+            };
+            self.start += usize::from(len);
+            self.last_len = usize::from(len);
+            self.offset = self.position;
+            self.position += u64::from(len);
+
+            match &res {
+                Ok(packet) => log!(trace, "itm::decode", "offset={} {:?}", self.offset, packet),
+                Err(e) => log!(debug, "itm::decode", "offset={} {}", self.offset, e),
+            }
+
+            if let Some(threshold) = self.config.resync_after {
+                self.recent_errors = (self.recent_errors << 1) | u16::from(res.is_err());
+
+                if self.recent_errors.count_ones() >= threshold {
+                    log!(
+                        debug,
+                        "itm::resync",
+                        "offset={} too many errors; waiting for a synchronization packet",
+                        self.offset
+                    );
+
+                    self.recent_errors = 0;
+                    self.stats.resyncs += 1;
+                    self.waiting = true;
+                }
+            }
+
+            match res {
+                Ok(packet) if self.config.is_ignored(packet.kind()) => continue,
+                Err(Error::ReservedHeader { .. }) | Err(Error::UnknownExtension { .. }) => {
+                    match self.config.unknown_headers {
+                        HeaderPolicy::Report => return Some(res),
+                        HeaderPolicy::Skip => continue,
+                        HeaderPolicy::Abort => {
+                            self.aborted = true;
+                            return Some(res);
+                        }
+                    }
+                }
+                _ => return Some(res),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Decoder {
+    // discards the pending bytes that precede the next synchronization packet; returns `false` if
+    // more bytes are needed to find it
+    fn skip_to_sync(&mut self) -> bool {
+        let pending = &self.buffer[self.start..];
+
+        // a synchronization packet is at least five zero bytes followed by 0x80
+        let mut zeros = 0;
+        let mut skip = None;
+        for (i, &byte) in pending.iter().enumerate() {
+            if byte == 0 {
+                zeros += 1;
+            } else if byte == 0b1000_0000 && zeros >= 5 {
+                skip = Some(i - zeros.min(MAX_SYNC_ZEROS));
+                break;
+            } else {
+                zeros = 0;
+            }
+        }
+
+        // keep the trailing zeros, up to the longest run a synchronization packet can start with;
+        // they could be the start of one
+        let found = skip.is_some();
+        let skip = skip.unwrap_or(pending.len() - zeros.min(MAX_SYNC_ZEROS));
+
+        if skip != 0 {
+            log!(
+                debug,
+                "itm::resync",
+                "offset={} skipped {} bytes while waiting for a synchronization packet",
+                self.position,
+                skip
+            );
+        }
+
+        // keep the bytes of the last yielded item right before `start`; see `raw_bytes`
+        self.buffer.drain(self.start..self.start + skip);
+        self.position += skip as u64;
+        self.stats.bytes += skip as u64;
+        self.stats.skipped_bytes += skip as u64;
+        self.waiting = !found;
+
+        found
+    }
+
+    /// Drives `visitor` with all the packets (and errors) that can be decoded from the bytes fed so
+    /// far
+    pub fn visit<V>(&mut self, visitor: &mut V)
+    where
+        V: PacketVisitor + ?Sized,
+    {
+        for res in self {
+            match res {
+                Ok(packet) => packet.visit(visitor),
+                Err(e) => visitor.on_error(e),
+            }
+        }
+    }
+}
+
+/// Statistics about the input processed by a `Decoder`
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    bytes: u64,
+    malformed_packets: u64,
+    // one counter per `Kind`
+    packets: [u64; 15],
+    reserved_headers: u64,
+    resyncs: u64,
+    skipped_bytes: u64,
+    truncated_packets: u64,
+    unknown_extensions: u64,
+}
+
+#[cfg(feature = "std")]
+impl Stats {
+    /// Number of bytes consumed, including the bytes of malformed packets and skipped bytes
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Number of decoded packets of the given `kind`, including ignored packets
+    pub fn packets(&self, kind: Kind) -> u64 {
+        self.packets[kind as usize]
+    }
+
+    /// Total number of decoded packets, including ignored packets
+    pub fn total_packets(&self) -> u64 {
+        self.packets.iter().sum()
+    }
+
+    /// Number of overflow packets
+    ///
+    /// Each overflow packet indicates that some trace data was lost
+    pub fn overflows(&self) -> u64 {
+        self.packets(Kind::Overflow)
+    }
+
+    /// Number of packets that started with a reserved header byte
+    pub fn reserved_headers(&self) -> u64 {
+        self.reserved_headers
+    }
+
+    /// Number of malformed packets
+    pub fn malformed_packets(&self) -> u64 {
+        self.malformed_packets
+    }
+
+    /// Number of times the decoder lost synchronization and started looking for a
+    /// synchronization packet
+    ///
+    /// See `DecoderBuilder::resync_after`
+    pub fn resyncs(&self) -> u64 {
+        self.resyncs
+    }
+
+    /// Number of bytes that were discarded without being decoded
+    ///
+    /// See `DecoderBuilder::wait_for_sync` and `DecoderBuilder::resync_after`
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes
+    }
+
+    /// Number of packets that were cut short by the end of the input
+    pub fn truncated_packets(&self) -> u64 {
+        self.truncated_packets
+    }
+
+    /// Number of extension packets that were skipped because they are unknown to this crate
+    pub fn unknown_extensions(&self) -> u64 {
+        self.unknown_extensions
+    }
+
+    fn record_packet(&mut self, packet: &Packet) {
+        self.bytes += u64::from(packet.len());
+        self.packets[packet.kind() as usize] += 1;
+    }
+
+    fn record_error(&mut self, error: &Error) {
+        self.bytes += u64::from(error.len());
+
+        match error {
+            Error::ReservedHeader { .. } => self.reserved_headers += 1,
+            Error::MalformedPacket { .. } => self.malformed_packets += 1,
+            Error::Truncated { .. } => self.truncated_packets += 1,
+            Error::UnknownExtension { .. } => self.unknown_extensions += 1,
+            Error::Io(_) => {}
+        }
+    }
+}
+
+/// Builds a `Decoder` with non-default settings
+///
+/// ```
+/// use itm::{packet::Kind, Decoder};
+///
+/// let decoder = Decoder::builder()
+///     .ignore(Kind::Synchronization)
+///     .ignore(Kind::PeriodicPcSample)
+///     .build();
+/// ```
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+pub struct DecoderBuilder {
+    config: Config,
+}
+
+#[cfg(feature = "std")]
+impl DecoderBuilder {
+    /// Creates a builder with the default settings: all packets are decoded
+    pub fn new() -> DecoderBuilder {
+        DecoderBuilder::default()
+    }
+
+    /// Don't yield packets of this kind
+    ///
+    /// The packets are still consumed from the input
+    pub fn ignore(mut self, kind: Kind) -> DecoderBuilder {
+        self.config.ignored |= kind.mask();
+        self
+    }
+
+    /// Only accept hardware source packets that are defined by the specification
+    ///
+    /// By default the decoder is permissive: hardware source packets with a reserved
+    /// discriminator, or with a payload size that doesn't match their discriminator, are yielded
+    /// as `Packet::HardwareSource`. A strict decoder reports them as `Error::MalformedPacket`s,
+    /// which cover the whole packet, instead; this is useful to validate the trace output of a
+    /// target.
+    ///
+    /// This is the only deviation from the specification that the permissive decoder tolerates.
+    /// Reserved headers, unknown extension packets and packets with invalid payloads are reported
+    /// as errors in both modes.
+    pub fn strict(mut self) -> DecoderBuilder {
+        self.config.strict = true;
+        self
+    }
+
+    /// The architecture of the target that produced the trace
+    ///
+    /// The default is `Architecture::ARMv8M`, whose packets are a superset of the ARMv7-M ones.
+    /// With `Architecture::ARMv7M` the encodings that only ARMv8-M defines, the data trace match
+    /// packets, are reported as `Error::MalformedPacket`s that cover the whole packet.
+    pub fn architecture(mut self, architecture: Architecture) -> DecoderBuilder {
+        self.config.architecture = architecture;
+        self
+    }
+
+    /// The framing of the input
+    ///
+    /// The default is `Format::Raw`. With `Format::Tpiu` the bytes fed into the decoder are TPIU
+    /// formatter frames: they go through a `tpiu::Deframer` and only the data of trace source 1,
+    /// the ID the ITM is usually configured with, is decoded. Offsets, `raw_bytes` and the
+    /// statistics then refer to the ITM data rather than to the framed input. Use a `Deframer`
+    /// directly to decode an ITM configured with a different trace source ID.
+    ///
+    /// `tpiu::detect` can pick the framing from the first bytes of a capture.
+    pub fn framing(mut self, format: Format) -> DecoderBuilder {
+        self.config.framing = format;
+        self
+    }
+
+    /// Discard the input until the first synchronization packet
+    ///
+    /// Use this when attaching to a live trace stream: the first bytes received are likely to be
+    /// the middle of a packet. The discarded bytes are counted in `Stats::skipped_bytes`; the
+    /// synchronization packet is the first packet decoded.
+    pub fn wait_for_sync(mut self) -> DecoderBuilder {
+        self.config.wait_for_sync = true;
+        self
+    }
+
+    /// What to do with reserved header bytes and unknown extension packets
+    ///
+    /// The default is `HeaderPolicy::Report`
+    pub fn unknown_headers(mut self, policy: HeaderPolicy) -> DecoderBuilder {
+        self.config.unknown_headers = policy;
+        self
+    }
+
+    /// Resynchronize after a burst of decoding errors
+    ///
+    /// Corrupted input (e.g. a glitch on the SWO line) can make the decoder lose track of the
+    /// packet boundaries, which shows as a mix of errors and bogus packets. When at least `errors`
+    /// of the last 16 items yielded by the decoder are errors the decoder discards the input until
+    /// the next synchronization packet, as `wait_for_sync` does, and then resumes decoding. Each
+    /// occurrence is counted in `Stats::resyncs`.
+    ///
+    /// Targets only emit synchronization packets periodically, if at all (see the `SYNCENA` bit
+    /// of `ITM_TCR`), so some valid data may be discarded.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `errors` is not in the range `1..=16`
+    pub fn resync_after(mut self, errors: u32) -> DecoderBuilder {
+        assert!((1..=16).contains(&errors), "invalid error threshold");
+
+        self.config.resync_after = Some(errors);
+        self
+    }
+
+    /// Creates the decoder
+    pub fn build(&self) -> Decoder {
+        Decoder {
+            config: self.config.clone(),
+            waiting: self.config.wait_for_sync,
+            ..Decoder::default()
+        }
+    }
+}
+
+// trace source ID of the ITM data in TPIU framed input
+#[cfg(feature = "std")]
+const ITM_ID: u8 = 1;
+
+// Decoder settings
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, Default)]
+struct Config {
+    architecture: Architecture,
+    framing: Format,
+    // bitmask of the packet `Kind`s that are not yielded
+    ignored: u16,
+    // report packets not defined by the specification as errors
+    strict: bool,
+    // number of errors, among the last 16 items, that triggers a resynchronization
+    resync_after: Option<u32>,
+    unknown_headers: HeaderPolicy,
+    // discard the input until the first synchronization packet
+    wait_for_sync: bool,
+}
+
+/// The architecture of the target that produced an ITM stream
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Architecture {
+    /// ARMv7-M (e.g. Cortex-M3, Cortex-M4 and Cortex-M7)
+    ARMv7M,
+    /// ARMv8-M (e.g. Cortex-M23, Cortex-M33 and Cortex-M55)
+    #[default]
+    ARMv8M,
+}
+
+/// What a `Decoder` does when it finds a header it doesn't recognize
+///
+/// This covers the `Error::ReservedHeader` and `Error::UnknownExtension` errors. Either way the
+/// error is counted in the decoder `Stats` and logged.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum HeaderPolicy {
+    /// Yield the error and continue decoding after it
+    #[default]
+    Report,
+    /// Continue decoding after the error without yielding it
+    Skip,
+    /// Yield the error and stop decoding
+    ///
+    /// The decoder yields no more packets, and `finish` reports no error, until it's `reset`.
+    /// The bytes that follow the error are left in `pending_bytes`
+    Abort,
+}
+
+#[cfg(feature = "std")]
+impl Config {
+    fn is_ignored(&self, kind: Kind) -> bool {
+        self.ignored & kind.mask() != 0
+    }
+}
+
+/// Callbacks invoked on decoded packets
+///
+/// All the methods have a default implementation that does nothing so implementers only need to
+/// override the ones they are interested in. Use `Packet::visit` or `Decoder::visit` to drive a
+/// visitor.
+pub trait PacketVisitor {
+    /// Called on overflow packets
+    fn on_overflow(&mut self) {}
+
+    /// Called on synchronization packets
+    fn on_synchronization(&mut self, _packet: Synchronization) {}
+
+    /// Called on instrumentation packets
+    fn on_instrumentation(&mut self, _packet: Instrumentation) {}
+
+    /// Called on local timestamp packets
+    fn on_local_timestamp(&mut self, _packet: LocalTimestamp) {}
+
+    /// Called on global timestamp packets (format 1)
+    fn on_gts1(&mut self, _packet: GTS1) {}
+
+    /// Called on global timestamp packets (format 2)
+    fn on_gts2(&mut self, _packet: GTS2) {}
+
+    /// Called on stimulus port page packets
+    fn on_stimulus_port_page(&mut self, _packet: StimulusPortPage) {}
+
+    /// Called on event counter packets
+    fn on_event_counter(&mut self, _packet: EventCounter) {}
+
+    /// Called on exception trace packets
+    fn on_exception_trace(&mut self, _packet: ExceptionTrace) {}
+
+    /// Called on periodic PC sample packets
+    fn on_periodic_pc_sample(&mut self, _packet: PeriodicPcSample) {}
+
+    /// Called on data trace PC value packets
+    fn on_data_trace_pc_value(&mut self, _packet: DataTracePcValue) {}
+
+    /// Called on data trace address packets
+    fn on_data_trace_address(&mut self, _packet: DataTraceAddress) {}
+
+    /// Called on data trace data value packets
+    fn on_data_trace_data_value(&mut self, _packet: DataTraceDataValue) {}
+
+    /// Called on data trace match packets
+    fn on_data_trace_match(&mut self, _packet: DataTraceMatch) {}
+
+    /// Called on other hardware source packets
+    fn on_hardware_source(&mut self, _packet: HardwareSource) {}
+
+    /// Called on decoding errors
+    fn on_error(&mut self, _error: Error) {}
+}
+
+/// Decodes the ITM packet at the start of `bytes`
+///
+/// Returns the packet and the number of bytes it spans. On errors `Error::len` is the number of
+/// bytes to skip to get to the next packet. If `bytes` ends before the packet does
+/// `Error::Truncated` is returned.
+///
+/// This function doesn't keep state between calls so instrumentation packets are reported on
+/// stimulus port page 0.
+///
+/// # Panics
+///
+/// This function panics if `bytes` is empty
+pub fn parse_one(bytes: &[u8]) -> Result<(Packet, usize), Error> {
+    assert!(!bytes.is_empty(), "no bytes to parse");
+
+    match parse(bytes) {
+        Ok(packet) => Ok((packet, usize::from(packet.len()))),
+        Err(Either::Left(e)) => Err(e),
+        Err(Either::Right(NeedMoreBytes)) => Err(Error::Truncated {
+            header: bytes[0],
+            len: bytes.len() as u8,
+        }),
+    }
+}
+
+/// Decodes ITM packets from a byte slice
+///
+/// This iterator doesn't copy the input nor allocate. It stops when it reaches the end of the
+/// slice or when the remaining bytes are the start of a packet that has not been fully received;
+/// `remainder` returns those bytes so they can be prepended to the next chunk of data.
+///
+/// The stimulus port page selected by Stimulus Port Page packets is tracked within each
+/// `SliceDecoder`; a new decoder starts at page 0.
+#[derive(Clone, Debug)]
+pub struct SliceDecoder<'a> {
+    bytes: &'a [u8],
+    // stimulus port page selected by the last Stimulus Port Page packet
+    page: u8,
+}
+
+impl<'a> SliceDecoder<'a> {
+    /// Creates a decoder that reads packets from the given `bytes`
+    pub fn new(bytes: &'a [u8]) -> SliceDecoder<'a> {
+        SliceDecoder { bytes, page: 0 }
+    }
+
+    /// Decodes packets into the slots of `out` until `out` is full or no more packets can be
+    /// decoded; returns the number of slots that were filled
+    ///
+    /// This never allocates, which makes it suitable for capture loops that need predictable
+    /// memory usage. Slots past the returned count are left untouched.
+    ///
+    /// ```
+    /// use itm::SliceDecoder;
+    ///
+    /// let mut out = [const { None }; 16];
+    /// let mut decoder = SliceDecoder::new(&[0x70, 0x01, b'A', 0x03, 0x10]);
+    ///
+    /// let n = decoder.decode_into(&mut out);
+    /// assert_eq!(n, 2);
+    /// // start of an incomplete packet
+    /// assert_eq!(decoder.remainder(), &[0x03, 0x10]);
+    /// ```
+    pub fn decode_into(&mut self, out: &mut [Option<Result<Packet, Error>>]) -> usize {
+        let mut n = 0;
+        for slot in out {
+            match self.next() {
+                Some(res) => *slot = Some(res),
+                None => break,
+            }
+
+            n += 1;
+        }
+        n
+    }
+
+    /// Returns the bytes that have not been decoded yet
+    pub fn remainder(&self) -> &'a [u8] {
+        self.bytes
+    }
+}
+
+impl<'a> Iterator for SliceDecoder<'a> {
+    type Item = Result<Packet, Error>;
+
+    fn next(&mut self) -> Option<Result<Packet, Error>> {
+        let (res, len) = match parse(self.bytes) {
+            Ok(mut packet) => {
+                track_page(&mut self.page, &mut packet);
+                (Ok(packet), packet.len())
+            }
+            // skip malformed packet
+            Err(Either::Left(e)) => {
+                let len = e.len();
+                (Err(e), len)
+            }
+            Err(Either::Right(NeedMoreBytes)) => return None,
+        };
+
+        self.bytes = &self.bytes[usize::from(len)..];
+
+        Some(res)
     }
 }
 
 /// ITM packet decoding errors
+///
+/// New variants may be added in minor releases
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     /// The packet starts with a reserved header byte
+    ///
+    /// Reserved header bytes usually indicate data corruption, e.g. a wrong SWO baud rate
     #[error("reserved header byte: {byte}")]
     ReservedHeader {
         /// The header byte
@@ -183,19 +1217,56 @@ pub enum Error {
         /// Length of the malformed packet in bytes, including the header
         len: u8,
     },
+
+    /// The packet is an extension packet that this crate doesn't know about
+    ///
+    /// Unlike the other errors, this is not a sign of data corruption: the encoding is valid but
+    /// it's not defined by the ARMv7-M specification
+    #[error("unknown extension packet of length {len} with header {header}")]
+    UnknownExtension {
+        /// The header of the extension packet
+        header: u8,
+        /// Length of the extension packet in bytes, including the header
+        len: u8,
+    },
+
+    /// The input ended in the middle of a packet
+    #[error("truncated packet of length {len} with header {header}")]
+    Truncated {
+        /// The header of the truncated packet
+        header: u8,
+        /// Number of bytes of the packet that were received, including the header
+        len: u8,
+    },
+
+    /// I/O error while reading the ITM stream
+    #[cfg(feature = "std")]
+    #[error("I/O error")]
+    Io(#[from] io::Error),
 }
 
 impl Error {
-    fn len(&self) -> u8 {
+    /// The number of input bytes covered by this error, including the header
+    ///
+    /// This is the number of bytes that were skipped. It's zero for `Error::Io`
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u8 {
         match *self {
             Error::ReservedHeader { .. } => 1,
-            Error::MalformedPacket { len, .. } => len,
+            Error::MalformedPacket { len, .. }
+            | Error::UnknownExtension { len, .. }
+            | Error::Truncated { len, .. } => len,
+            // not produced by the parser
+            #[cfg(feature = "std")]
+            Error::Io(_) => 0,
         }
     }
 }
 
 /// An ITM packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum Packet {
     /// Overflow packet
     Overflow,
@@ -221,17 +1292,68 @@ pub enum Packet {
     DataTracePcValue(DataTracePcValue),
     /// Data Trace Address
     DataTraceAddress(DataTraceAddress),
-    /// Data Trace Address
+    /// Data Trace Data Value
     DataTraceDataValue(DataTraceDataValue),
+    /// Data Trace Match (ARMv8-M)
+    DataTraceMatch(DataTraceMatch),
+    /// Other hardware source packet
+    HardwareSource(HardwareSource),
 }
 
 impl Packet {
+    /// The kind of this packet
+    pub fn kind(&self) -> Kind {
+        match *self {
+            Packet::Overflow => Kind::Overflow,
+            Packet::Synchronization(_) => Kind::Synchronization,
+            Packet::Instrumentation(_) => Kind::Instrumentation,
+            Packet::LocalTimestamp(_) => Kind::LocalTimestamp,
+            Packet::GTS1(_) => Kind::GTS1,
+            Packet::GTS2(_) => Kind::GTS2,
+            Packet::StimulusPortPage(_) => Kind::StimulusPortPage,
+            Packet::EventCounter(_) => Kind::EventCounter,
+            Packet::ExceptionTrace(_) => Kind::ExceptionTrace,
+            Packet::PeriodicPcSample(_) => Kind::PeriodicPcSample,
+            Packet::DataTracePcValue(_) => Kind::DataTracePcValue,
+            Packet::DataTraceAddress(_) => Kind::DataTraceAddress,
+            Packet::DataTraceDataValue(_) => Kind::DataTraceDataValue,
+            Packet::DataTraceMatch(_) => Kind::DataTraceMatch,
+            Packet::HardwareSource(_) => Kind::HardwareSource,
+        }
+    }
+
+    /// The category of this packet: protocol, instrumentation or hardware source packet
+    pub fn source(&self) -> Source {
+        self.kind().source()
+    }
+
+    /// The DWT comparator that generated this packet, if this is a data trace packet
+    ///
+    /// ```
+    /// use itm::{packet::DataTraceDataValue, Packet};
+    ///
+    /// let labels = ["heap_ptr", "stack_ptr", "state", "counter"];
+    ///
+    /// let packet = Packet::from(DataTraceDataValue::new(0, true, &[0x00, 0x04, 0x00, 0x20]));
+    /// assert_eq!(packet.comparator().map(|n| labels[usize::from(n)]), Some("heap_ptr"));
+    /// ```
+    pub fn comparator(&self) -> Option<u8> {
+        match *self {
+            Packet::DataTracePcValue(p) => Some(p.comparator()),
+            Packet::DataTraceAddress(p) => Some(p.comparator()),
+            Packet::DataTraceDataValue(p) => Some(p.comparator()),
+            Packet::DataTraceMatch(p) => Some(p.comparator()),
+            _ => None,
+        }
+    }
+
     /// The length of this packet in bytes, including the header
-    fn len(&self) -> u8 {
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u8 {
         match *self {
             Packet::Overflow => 1,
             Packet::Synchronization(s) => s.len(),
-            Packet::Instrumentation(i) => 1 /* header */ + i.size,
+            Packet::Instrumentation(i) => 1 /* header */ + i.payload.len(),
             Packet::LocalTimestamp(lt) => lt.len,
             Packet::GTS1(gt) => gt.len,
             Packet::GTS2(gt) => {
@@ -253,11 +1375,307 @@ impl Packet {
             }
             Packet::DataTracePcValue(_) => 5,
             Packet::DataTraceAddress(_) => 3,
-            Packet::DataTraceDataValue(dtdv) => 1 /* header */ + dtdv.size,
+            Packet::DataTraceDataValue(dtdv) => 1 /* header */ + dtdv.value.len(),
+            Packet::DataTraceMatch(_) => 2,
+            Packet::HardwareSource(hs) => 1 /* header */ + hs.payload.len(),
+        }
+    }
+
+    /// Calls the `visitor` method that corresponds to this packet
+    pub fn visit<V>(&self, visitor: &mut V)
+    where
+        V: PacketVisitor + ?Sized,
+    {
+        match *self {
+            Packet::Overflow => visitor.on_overflow(),
+            Packet::Synchronization(p) => visitor.on_synchronization(p),
+            Packet::Instrumentation(p) => visitor.on_instrumentation(p),
+            Packet::LocalTimestamp(p) => visitor.on_local_timestamp(p),
+            Packet::GTS1(p) => visitor.on_gts1(p),
+            Packet::GTS2(p) => visitor.on_gts2(p),
+            Packet::StimulusPortPage(p) => visitor.on_stimulus_port_page(p),
+            Packet::EventCounter(p) => visitor.on_event_counter(p),
+            Packet::ExceptionTrace(p) => visitor.on_exception_trace(p),
+            Packet::PeriodicPcSample(p) => visitor.on_periodic_pc_sample(p),
+            Packet::DataTracePcValue(p) => visitor.on_data_trace_pc_value(p),
+            Packet::DataTraceAddress(p) => visitor.on_data_trace_address(p),
+            Packet::DataTraceDataValue(p) => visitor.on_data_trace_data_value(p),
+            Packet::DataTraceMatch(p) => visitor.on_data_trace_match(p),
+            Packet::HardwareSource(p) => visitor.on_hardware_source(p),
+        }
+    }
+
+    /// Encodes this packet into the start of the given `buffer`
+    ///
+    /// Returns the number of bytes written, which is always `self.len()`. Decoding those bytes
+    /// produces a packet equal to `self`
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `buffer` is shorter than `self.len()`
+    pub fn encode(&self, buffer: &mut [u8]) -> usize {
+        let len = usize::from(self.len());
+        let (header, payload) = buffer[..len]
+            .split_first_mut()
+            .expect("buffer is too short");
+
+        *header = match *self {
+            Packet::Overflow => 0b0111_0000,
+
+            Packet::Synchronization(_) => {
+                // "Synchronization packet is at least forty-seven 0 bits followed by single 1 bit"
+                for byte in payload.iter_mut() {
+                    *byte = 0b0000_0000;
+                }
+                payload[payload.len() - 1] = 0b1000_0000;
+
+                0b0000_0000
+            }
+
+            Packet::Instrumentation(i) => {
+                payload.copy_from_slice(i.payload());
+
+                // 0bAAAA_A0SS
+                (i.port << 3) | encode_size(i.payload.len())
+            }
+
+            Packet::LocalTimestamp(lt) => {
+                if lt.len == 1 {
+                    // 0b0TS_0000 (LTS2)
+                    (lt.delta as u8) << 4
+                } else {
+                    encode_continued(u64::from(lt.delta), payload);
+
+                    // 0b11TC_0000 (LTS1)
+                    0b1100_0000 | (lt.tc << 4)
+                }
+            }
+
+            Packet::GTS1(gt) => {
+                encode_continued(u64::from(gt.bits), payload);
+
+                if payload.len() == 4 {
+                    // the fourth payload byte has extra info
+                    payload[3] |= (u8::from(gt.clk_ch) << 5) | (u8::from(gt.wrap) << 6);
+                }
+
+                0b1001_0100
+            }
+
+            Packet::GTS2(gt) => {
+                encode_continued(gt.bits, payload);
+
+                0b1011_0100
+            }
+
+            // 0b0xxx_1000
+            Packet::StimulusPortPage(spp) => (spp.page << 4) | 0b0000_1000,
+
+            Packet::EventCounter(ec) => {
+                payload[0] = ec.payload;
+
+                0b0000_0101
+            }
+
+            Packet::ExceptionTrace(et) => {
+                let function = match et.function {
+                    Function::Enter => 0b000_1000,
+                    Function::Exit => 0b001_0000,
+                    Function::Return => 0b001_1000,
+                };
+
+                payload[0] = et.number as u8;
+                payload[1] = (function << 1) | (et.number >> 8) as u8;
+
+                0b0000_1110
+            }
+
+            Packet::PeriodicPcSample(pps) => match pps.pc {
+                Some(pc) => {
+                    LE::write_u32(payload, pc);
+
+                    0b0001_0111
+                }
+                None => {
+                    payload[0] = 0;
+
+                    0b0001_0101
+                }
+            },
+
+            Packet::DataTracePcValue(dtpv) => {
+                LE::write_u32(payload, dtpv.pc);
+
+                // 0b01xx_0111
+                0b0100_0111 | (dtpv.cmpn << 4)
+            }
+
+            Packet::DataTraceAddress(dta) => {
+                LE::write_u16(payload, dta.address);
+
+                // 0b01xx_1110
+                0b0100_1110 | (dta.cmpn << 4)
+            }
+
+            Packet::DataTraceDataValue(dtdv) => {
+                payload.copy_from_slice(dtdv.value());
+
+                // 0b10xx_W1SS
+                0b1000_0100
+                    | (dtdv.cmpn << 4)
+                    | (u8::from(dtdv.wnr) << 3)
+                    | encode_size(dtdv.value.len())
+            }
+
+            Packet::DataTraceMatch(dtm) => {
+                payload[0] = 0b1;
+
+                // 0b01xx_0101
+                0b0100_0101 | (dtm.cmpn << 4)
+            }
+
+            Packet::HardwareSource(hs) => {
+                payload.copy_from_slice(hs.payload());
+
+                // 0bAAAA_A1SS
+                (hs.disc << 3) | 0b100 | encode_size(hs.payload.len())
+            }
+        };
+
+        len
+    }
+
+    /// Encodes this packet into a vector of bytes
+    ///
+    /// See `encode` for details
+    #[cfg(feature = "std")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; usize::from(self.len())];
+        self.encode(&mut bytes);
+        bytes
+    }
+}
+
+impl From<Synchronization> for Packet {
+    fn from(p: Synchronization) -> Packet {
+        Packet::Synchronization(p)
+    }
+}
+
+impl From<Instrumentation> for Packet {
+    fn from(p: Instrumentation) -> Packet {
+        Packet::Instrumentation(p)
+    }
+}
+
+impl From<LocalTimestamp> for Packet {
+    fn from(p: LocalTimestamp) -> Packet {
+        Packet::LocalTimestamp(p)
+    }
+}
+
+impl From<GTS1> for Packet {
+    fn from(p: GTS1) -> Packet {
+        Packet::GTS1(p)
+    }
+}
+
+impl From<GTS2> for Packet {
+    fn from(p: GTS2) -> Packet {
+        Packet::GTS2(p)
+    }
+}
+
+impl From<StimulusPortPage> for Packet {
+    fn from(p: StimulusPortPage) -> Packet {
+        Packet::StimulusPortPage(p)
+    }
+}
+
+impl From<EventCounter> for Packet {
+    fn from(p: EventCounter) -> Packet {
+        Packet::EventCounter(p)
+    }
+}
+
+impl From<ExceptionTrace> for Packet {
+    fn from(p: ExceptionTrace) -> Packet {
+        Packet::ExceptionTrace(p)
+    }
+}
+
+impl From<PeriodicPcSample> for Packet {
+    fn from(p: PeriodicPcSample) -> Packet {
+        Packet::PeriodicPcSample(p)
+    }
+}
+
+impl From<DataTracePcValue> for Packet {
+    fn from(p: DataTracePcValue) -> Packet {
+        Packet::DataTracePcValue(p)
+    }
+}
+
+impl From<DataTraceAddress> for Packet {
+    fn from(p: DataTraceAddress) -> Packet {
+        Packet::DataTraceAddress(p)
+    }
+}
+
+impl From<DataTraceDataValue> for Packet {
+    fn from(p: DataTraceDataValue) -> Packet {
+        Packet::DataTraceDataValue(p)
+    }
+}
+
+impl From<DataTraceMatch> for Packet {
+    fn from(p: DataTraceMatch) -> Packet {
+        Packet::DataTraceMatch(p)
+    }
+}
+
+impl From<HardwareSource> for Packet {
+    fn from(p: HardwareSource) -> Packet {
+        Packet::HardwareSource(p)
+    }
+}
+
+// encodes a payload size (1, 2 or 4 bytes) into the SS bits of a header
+fn encode_size(size: u8) -> u8 {
+    match size {
+        1 => 0b01,
+        2 => 0b10,
+        _ => 0b11,
+    }
+}
+
+// splits `value` into 7-bit chunks, one per `payload` byte, and sets the C (Continue) bit of all the
+// bytes but the last one
+fn encode_continued(value: u64, payload: &mut [u8]) {
+    let last = payload.len() - 1;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte = ((value >> (7 * i)) & 0b0111_1111) as u8;
+
+        if i != last {
+            *byte |= 0b1000_0000;
         }
     }
 }
 
+// tracks the page selected by Stimulus Port Page packets and applies it to the instrumentation
+// packets that follow them
+fn track_page(page: &mut u8, packet: &mut Packet) {
+    match packet {
+        Packet::StimulusPortPage(spp) => *page = spp.page,
+        Packet::Instrumentation(i) => i.page = *page,
+        _ => {}
+    }
+}
+
+// the longest run of zero bytes, header included, that a synchronization packet can start with;
+// the length of the packet must fit in a `u8`
+const MAX_SYNC_ZEROS: usize = u8::MAX as usize - 1;
+
 /// Tries to parse an ITM packet from the start of the given buffer
 fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
     let header = input.first().cloned().ok_or(Either::Right(NeedMoreBytes))?;
@@ -268,7 +1686,9 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
 
             loop {
                 match input.get(usize::from(cursor)) {
-                    Some(&0b0000_0000) => {
+                    // NOTE the length of the packet must fit in a `u8`; longer runs of zeros are
+                    // reported as malformed packets
+                    Some(&0b0000_0000) if usize::from(cursor) < MAX_SYNC_ZEROS => {
                         // still within the synchronization packet
                         cursor += 1;
                         continue;
@@ -298,15 +1718,11 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
         Header::Overflow => Ok(Packet::Overflow),
 
         Header::Instrumentation { port, size } => {
-            let mut buffer = [0; 4];
-
             let usize = usize::from(size);
             if input.len() > usize {
-                buffer[..usize].copy_from_slice(&input[1..=usize]);
-
                 Ok(Packet::Instrumentation(Instrumentation {
-                    buffer,
-                    size,
+                    page: 0,
+                    payload: Payload::new(&input[1..=usize]),
                     port,
                 }))
             } else {
@@ -459,6 +1875,14 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
                             len: cursor,
                         }));
                     }
+                } else if cursor == 6 {
+                    // payloads are at most 6 bytes in size; as in GTS1 packets the last byte could
+                    // be a new header so we consider the malformed packet to end at the fifth
+                    // payload byte
+                    return Err(Either::Left(Error::MalformedPacket {
+                        header,
+                        len: cursor,
+                    }));
                 } else {
                     // Continue (C) bit is one
                     cursor += 1;
@@ -472,6 +1896,33 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
             Ok(Packet::StimulusPortPage(StimulusPortPage { page }))
         }
 
+        Header::Extension => {
+            // skip the payload; its C (Continue) bits are like the ones of LTS1 payloads except
+            // that the fourth payload byte, if any, is always the last one
+            let mut cursor = 1u8;
+            if header & 0b1000_0000 != 0 {
+                loop {
+                    let payload = input
+                        .get(usize::from(cursor))
+                        .cloned()
+                        .ok_or(Either::Right(NeedMoreBytes))?;
+
+                    if payload & 0b1000_0000 == 0 || cursor == 4 {
+                        break;
+                    }
+
+                    cursor += 1;
+                }
+
+                cursor += 1;
+            }
+
+            Err(Either::Left(Error::UnknownExtension {
+                header,
+                len: cursor,
+            }))
+        }
+
         Header::EventCounter => {
             let payload = input.get(1).cloned().ok_or(Either::Right(NeedMoreBytes))?;
 
@@ -549,22 +2000,40 @@ fn parse(input: &[u8]) -> Result<Packet, Either<Error, NeedMoreBytes>> {
         }
 
         Header::DataTraceDataValue { cmpn, wnr, size } => {
-            let mut buffer = [0; 4];
-
             let usize = usize::from(size);
             if input.len() > usize {
-                buffer[..usize].copy_from_slice(&input[1..=usize]);
-
                 Ok(Packet::DataTraceDataValue(DataTraceDataValue {
-                    buffer,
                     cmpn,
-                    size,
+                    value: Payload::new(&input[1..=usize]),
                     wnr,
                 }))
             } else {
                 Err(Either::Right(NeedMoreBytes))
             }
         }
+
+        Header::DataTraceMatch { cmpn } => {
+            let payload = input.get(1).cloned().ok_or(Either::Right(NeedMoreBytes))?;
+
+            if payload == 0b1 {
+                Ok(Packet::DataTraceMatch(DataTraceMatch { cmpn }))
+            } else {
+                // assume that the payload was lost
+                Err(Either::Left(Error::MalformedPacket { header, len: 1 }))
+            }
+        }
+
+        Header::HardwareSource { disc, size } => {
+            let usize = usize::from(size);
+            if input.len() > usize {
+                Ok(Packet::HardwareSource(HardwareSource {
+                    disc,
+                    payload: Payload::new(&input[1..=usize]),
+                }))
+            } else {
+                Err(Either::Right(NeedMoreBytes))
+            }
+        }
     }
 }
 
@@ -601,6 +2070,8 @@ enum Header {
     StimulusPortPage {
         page: u8,
     },
+    /// D4.2.6 Any other extension packet
+    Extension,
 
     // D4.3 Hardware Source Packets
     /// D4.3.1 Event counter packet
@@ -625,6 +2096,15 @@ enum Header {
         wnr: bool,
         size: u8,
     },
+    /// Data trace match packet (ARMv8-M)
+    DataTraceMatch {
+        cmpn: u8,
+    },
+    /// D4.3 Hardware source packet of any other type
+    HardwareSource {
+        disc: u8,
+        size: u8,
+    },
 }
 
 impl Header {
@@ -664,10 +2144,13 @@ impl Header {
                     let page = (byte >> 4) & 0b111;
 
                     Header::StimulusPortPage { page }
+                } else if byte & 0b0000_1011 == 0b0000_1000 {
+                    // 0bCxxx_1S00
+                    Header::Extension
                 } else {
                     // 0bAAAA_A0SS
                     match byte & 0b111 {
-                        0b001 | 0b010 | 0b011 => {
+                        0b001..=0b011 => {
                             let port = byte >> 3;
                             let size = match byte & 0b11 {
                                 0b01 => 1,
@@ -686,10 +2169,13 @@ impl Header {
                             } else if byte & 0b1100_1111 == 0b0100_1110 {
                                 // 0b01xx_1110
                                 Header::DataTraceAddress { cmpn }
+                            } else if byte & 0b1100_1111 == 0b0100_0101 {
+                                // 0b01xx_0101
+                                Header::DataTraceMatch { cmpn }
                             } else if byte & 0b1100_0100 == 0b1000_0100 {
                                 // 0b01xx_W1SS
                                 match byte & 0b11 {
-                                    0b01 | 0b10 | 0b11 => {
+                                    0b01..=0b11 => {
                                         let size = match byte & 0b11 {
                                             0b01 => 1,
                                             0b10 => 2,
@@ -706,6 +2192,19 @@ impl Header {
                                     }
                                     _ => unreachable!(),
                                 }
+                            } else if byte & 0b100 != 0 && byte & 0b11 != 0 {
+                                // 0bAAAA_A1SS
+                                let size = match byte & 0b11 {
+                                    0b01 => 1,
+                                    0b10 => 2,
+                                    0b11 => 4,
+                                    _ => unreachable!(),
+                                };
+
+                                Header::HardwareSource {
+                                    disc: byte >> 3,
+                                    size,
+                                }
                             } else {
                                 return Err(Error::ReservedHeader { byte });
                             }
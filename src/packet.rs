@@ -2,50 +2,241 @@
 
 use core::fmt;
 
+/// The kind of an ITM packet
+///
+/// This is `Packet` without the packet contents. See `Packet::kind`
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum Kind {
+    /// Overflow packet
+    Overflow,
+    /// Synchronization packet
+    Synchronization,
+    /// Instrumentation packet
+    Instrumentation,
+    /// Local timestamp packet
+    LocalTimestamp,
+    /// Global timestamp packet (format 1)
+    GTS1,
+    /// Global timestamp packet (format 2)
+    GTS2,
+    /// Stimulus Port Page (Extension packet)
+    StimulusPortPage,
+    /// Event Counter
+    EventCounter,
+    /// Exception Trace
+    ExceptionTrace,
+    /// Periodic PC Sample
+    PeriodicPcSample,
+    /// Data Trace PC Value
+    DataTracePcValue,
+    /// Data Trace Address
+    DataTraceAddress,
+    /// Data Trace Data Value
+    DataTraceDataValue,
+    /// Data Trace Match (ARMv8-M)
+    DataTraceMatch,
+    /// Other hardware source packet
+    HardwareSource,
+}
+
+impl Kind {
+    /// The source of the packets of this kind
+    pub fn source(self) -> Source {
+        match self {
+            Kind::Overflow
+            | Kind::Synchronization
+            | Kind::LocalTimestamp
+            | Kind::GTS1
+            | Kind::GTS2
+            | Kind::StimulusPortPage => Source::Protocol,
+            Kind::Instrumentation => Source::Instrumentation,
+            Kind::EventCounter
+            | Kind::ExceptionTrace
+            | Kind::PeriodicPcSample
+            | Kind::DataTracePcValue
+            | Kind::DataTraceAddress
+            | Kind::DataTraceDataValue
+            | Kind::DataTraceMatch
+            | Kind::HardwareSource => Source::Hardware,
+        }
+    }
+
+    // this kind as a bit in a bitmask
+    #[cfg(feature = "std")]
+    pub(crate) fn mask(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// The category of an ITM packet
+///
+/// See `Packet::source`
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum Source {
+    /// Packets that the ITM generates to structure the stream: synchronization, overflow,
+    /// timestamp and extension packets
+    Protocol,
+    /// Packets generated by software writes to the stimulus ports
+    Instrumentation,
+    /// Packets generated by the DWT unit: event counter, exception trace, PC sample and data trace
+    /// packets
+    Hardware,
+}
+
 /// Synchronization packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::Synchronization"))]
 pub struct Synchronization {
     pub(crate) len: u8,
 }
 
 impl Synchronization {
+    /// Creates a synchronization packet that's `len` bytes long, including the header
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `len` is less than 6
+    pub fn new(len: u8) -> Synchronization {
+        assert!(
+            len >= 6,
+            "synchronization packets are at least 6 bytes long"
+        );
+
+        Synchronization { len }
+    }
+
     /// The length in bytes of this synchronization packet
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u8 {
         self.len
     }
 }
 
 /// Instrumentation packet
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::Instrumentation"))]
 pub struct Instrumentation {
-    pub(crate) buffer: [u8; 4],
+    // selected by a preceding Stimulus Port Page packet
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) page: u8,
+    pub(crate) payload: Payload,
     pub(crate) port: u8,
-    pub(crate) size: u8,
 }
 
 impl Instrumentation {
-    /// The stimulus port that generated this packet
+    /// Creates an instrumentation packet from stimulus port `port` of page 0
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `port` is not in the range `0..32` or if `payload` is not 1, 2 or
+    /// 4 bytes long
+    pub fn new(port: u8, payload: &[u8]) -> Instrumentation {
+        assert!(port < 32, "invalid stimulus port");
+        assert!(
+            payload.len() == 1 || payload.len() == 2 || payload.len() == 4,
+            "invalid payload size"
+        );
+
+        Instrumentation {
+            page: 0,
+            payload: Payload::new(payload),
+            port,
+        }
+    }
+
+    /// Returns this packet with its stimulus port moved to the given `page`
+    ///
+    /// The page is not part of the encoding of the packet; it's selected by a preceding Stimulus
+    /// Port Page packet
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `page` is not in the range `0..8`
+    #[must_use]
+    pub fn with_page(self, page: u8) -> Instrumentation {
+        assert!(page < 8, "invalid stimulus port page");
+
+        Instrumentation { page, ..self }
+    }
+
+    /// The stimulus port that generated this packet, within its page
     pub fn port(&self) -> u8 {
         self.port
     }
 
+    /// The stimulus port page of the port that generated this packet
+    ///
+    /// This is the page selected by the last Stimulus Port Page packet decoded before this packet,
+    /// or 0 if there was none
+    pub fn page(&self) -> u8 {
+        self.page
+    }
+
+    /// The full number of the stimulus port that generated this packet: `page * 32 + port`
+    pub fn address(&self) -> u16 {
+        u16::from(self.page) * 32 + u16::from(self.port)
+    }
+
     /// The payload of this packet
     pub fn payload(&self) -> &[u8] {
-        &self.buffer[..usize::from(self.size)]
+        self.payload.as_slice()
+    }
+
+    /// The payload of a 2-byte write, as a little endian `u16`
+    ///
+    /// Returns `None` if the payload is not 2 bytes long
+    pub fn as_u16(&self) -> Option<u16> {
+        match *self.payload() {
+            [a, b] => Some(u16::from_le_bytes([a, b])),
+            _ => None,
+        }
+    }
+
+    /// The payload of a 4-byte write, as a little endian `u32`
+    ///
+    /// Returns `None` if the payload is not 4 bytes long
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self.payload() {
+            [a, b, c, d] => Some(u32::from_le_bytes([a, b, c, d])),
+            _ => None,
+        }
+    }
+
+    /// The payload of a 4-byte write, as a little endian `i32`
+    ///
+    /// Returns `None` if the payload is not 4 bytes long
+    pub fn as_i32(&self) -> Option<i32> {
+        self.as_u32().map(|x| x as i32)
+    }
+
+    /// The payload of a 4-byte write, as a little endian `f32`
+    ///
+    /// Returns `None` if the payload is not 4 bytes long
+    pub fn as_f32(&self) -> Option<f32> {
+        self.as_u32().map(f32::from_bits)
     }
 }
 
 impl fmt::Debug for Instrumentation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Instrumentation")
-            .field("payload", &&self.buffer[..usize::from(self.size)])
+            .field("page", &self.page)
+            .field("payload", &self.payload())
             .field("port", &self.port)
             .finish()
     }
 }
 
 /// Local timestamp packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::LocalTimestamp"))]
 pub struct LocalTimestamp {
     pub(crate) delta: u32,
     // TC[1:0] bits
@@ -55,6 +246,28 @@ pub struct LocalTimestamp {
 }
 
 impl LocalTimestamp {
+    /// Creates a local timestamp packet
+    ///
+    /// The packet will use the shortest encoding that can represent the given values
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `delta` doesn't fit in 28 bits
+    pub fn new(delta: u32, timestamp_delayed: bool, event_delayed: bool) -> LocalTimestamp {
+        assert!(delta < 1 << 28, "local timestamps are at most 28 bits");
+
+        let tc = u8::from(timestamp_delayed) | (u8::from(event_delayed) << 1);
+
+        let len = if tc == 0 && delta != 0 && delta < 0b111 {
+            // fits in a LTS2 packet
+            1
+        } else {
+            1 + continued_len(delta)
+        };
+
+        LocalTimestamp { delta, tc, len }
+    }
+
     /// The local timestamp value
     ///
     /// This is the interval since the previous Local timestamp packet
@@ -92,7 +305,9 @@ impl LocalTimestamp {
 }
 
 /// Global timestamp packet (format 1)
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::GTS1"))]
 pub struct GTS1 {
     pub(crate) bits: u32,
     pub(crate) clk_ch: bool,
@@ -102,6 +317,29 @@ pub struct GTS1 {
 }
 
 impl GTS1 {
+    /// Creates a global timestamp packet (format 1)
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `bits` doesn't fit in 26 bits
+    pub fn new(bits: u32, has_clock_changed: bool, has_wrapped: bool) -> GTS1 {
+        assert!(bits < 1 << 26, "GTS1 packets carry at most 26 bits");
+
+        let len = if has_clock_changed || has_wrapped {
+            // the flags are in the fourth payload byte
+            5
+        } else {
+            1 + continued_len(bits)
+        };
+
+        GTS1 {
+            bits,
+            clk_ch: has_clock_changed,
+            len,
+            wrap: has_wrapped,
+        }
+    }
+
     /// Timestamp bits (up to 26 bits)
     pub fn bits(&self) -> u32 {
         self.bits
@@ -124,13 +362,31 @@ impl GTS1 {
 }
 
 /// Global timestamp packet (format 2)
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::GTS2"))]
 pub struct GTS2 {
     pub(crate) bits: u64,
     pub(crate) b64: bool,
 }
 
 impl GTS2 {
+    /// Creates a global timestamp packet (format 2)
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `bits` doesn't fit in 22 bits (48-bit timestamps) or 38 bits (64-bit
+    /// timestamps)
+    pub fn new(bits: u64, is_64_bit: bool) -> GTS2 {
+        let width = if is_64_bit { 38 } else { 22 };
+        assert!(bits < 1 << width, "too many timestamp bits");
+
+        GTS2 {
+            bits,
+            b64: is_64_bit,
+        }
+    }
+
     /// High-order bits of the global timestamp
     pub fn bits(&self) -> u64 {
         self.bits
@@ -143,12 +399,25 @@ impl GTS2 {
 }
 
 /// Stimulus Port Page (Extension packet)
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::StimulusPortPage"))]
 pub struct StimulusPortPage {
     pub(crate) page: u8,
 }
 
 impl StimulusPortPage {
+    /// Creates a stimulus port page packet
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `page` doesn't fit in 3 bits
+    pub fn new(page: u8) -> StimulusPortPage {
+        assert!(page < 8, "invalid stimulus port page");
+
+        StimulusPortPage { page }
+    }
+
     /// Stimulus port page (3-bit value)
     pub fn page(&self) -> u8 {
         self.page
@@ -156,12 +425,33 @@ impl StimulusPortPage {
 }
 
 /// Event counter packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::EventCounter"))]
 pub struct EventCounter {
     pub(crate) payload: u8,
 }
 
 impl EventCounter {
+    /// Creates an event counter packet from the counters that wrapped around
+    pub fn new(
+        cpi: bool,
+        exc: bool,
+        sleep: bool,
+        lsu: bool,
+        fold: bool,
+        post: bool,
+    ) -> EventCounter {
+        EventCounter {
+            payload: u8::from(cpi)
+                | (u8::from(exc) << 1)
+                | (u8::from(sleep) << 2)
+                | (u8::from(lsu) << 3)
+                | (u8::from(fold) << 4)
+                | (u8::from(post) << 5),
+        }
+    }
+
     /// has CPICNT wrapped around?
     pub fn cpi(&self) -> bool {
         self.payload & 1 != 0
@@ -194,7 +484,9 @@ impl EventCounter {
 }
 
 /// The action taken by the processor
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum Function {
     /// Entered exception
     Enter,
@@ -205,31 +497,213 @@ pub enum Function {
 }
 
 /// Exception trace packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::ExceptionTrace"))]
 pub struct ExceptionTrace {
     pub(crate) function: Function,
     pub(crate) number: u16,
 }
 
 impl ExceptionTrace {
+    /// Creates an exception trace packet
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `number` doesn't fit in 9 bits
+    pub fn new(number: u16, function: Function) -> ExceptionTrace {
+        assert!(number < 1 << 9, "invalid exception number");
+
+        ExceptionTrace { function, number }
+    }
+
     /// Exception number
     pub fn number(&self) -> u16 {
         self.number
     }
 
+    /// The exception, identified by its number
+    pub fn exception(&self) -> Exception {
+        Exception::from_number(self.number)
+    }
+
     /// The action taken by the processor
     pub fn function(&self) -> Function {
         self.function
     }
 }
 
-/// Periodic PC sample packet
+/// An exception, as identified by the exception number of an exception trace packet
+///
+/// The `Display` implementation prints the architectural name of the exception, or `IRQ <n>` for
+/// external interrupts. Use `display_with` to show device-specific interrupt names.
+///
+/// ```
+/// use itm::packet::Exception;
+///
+/// let irq_names = ["WWDG", "PVD", "TAMP_STAMP"];
+/// let name = |irq: u16| irq_names.get(usize::from(irq)).cloned();
+///
+/// assert_eq!(Exception::from_number(15).display_with(name).to_string(), "SysTick");
+/// assert_eq!(Exception::from_number(17).display_with(name).to_string(), "PVD");
+/// assert_eq!(Exception::from_number(99).display_with(name).to_string(), "IRQ 83");
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::Exception"))]
+pub enum Exception {
+    /// No exception; exception number 0 is reported when the processor returns to Thread mode
+    ThreadMode,
+    /// Reset
+    Reset,
+    /// Non-maskable interrupt
+    NonMaskableInt,
+    /// All classes of fault
+    HardFault,
+    /// Memory management fault
+    MemoryManagement,
+    /// Bus fault
+    BusFault,
+    /// Usage fault
+    UsageFault,
+    /// Secure fault (ARMv8-M)
+    SecureFault,
+    /// Supervisor call
+    SVCall,
+    /// Debug monitor
+    DebugMonitor,
+    /// Pendable request for system service
+    PendSV,
+    /// System tick timer
+    SysTick,
+    /// External interrupt with the given IRQ number; this is the exception number minus 16
+    ///
+    /// Exception numbers are 9 bits so the IRQ number is at most 495
+    Interrupt(u16),
+    /// Reserved exception number
+    Reserved(u16),
+}
+
+impl Exception {
+    /// The exception identified by the given exception `number`
+    pub fn from_number(number: u16) -> Exception {
+        match number {
+            0 => Exception::ThreadMode,
+            1 => Exception::Reset,
+            2 => Exception::NonMaskableInt,
+            3 => Exception::HardFault,
+            4 => Exception::MemoryManagement,
+            5 => Exception::BusFault,
+            6 => Exception::UsageFault,
+            7 => Exception::SecureFault,
+            11 => Exception::SVCall,
+            12 => Exception::DebugMonitor,
+            14 => Exception::PendSV,
+            15 => Exception::SysTick,
+            16.. => Exception::Interrupt(number - 16),
+            _ => Exception::Reserved(number),
+        }
+    }
+
+    /// The exception number of this exception
+    ///
+    /// The number of an `Interrupt` whose IRQ number is greater than 495, which no exception trace
+    /// packet reports, wraps around
+    pub fn number(&self) -> u16 {
+        match *self {
+            Exception::ThreadMode => 0,
+            Exception::Reset => 1,
+            Exception::NonMaskableInt => 2,
+            Exception::HardFault => 3,
+            Exception::MemoryManagement => 4,
+            Exception::BusFault => 5,
+            Exception::UsageFault => 6,
+            Exception::SecureFault => 7,
+            Exception::SVCall => 11,
+            Exception::DebugMonitor => 12,
+            Exception::PendSV => 14,
+            Exception::SysTick => 15,
+            Exception::Interrupt(irq) => irq.wrapping_add(16),
+            Exception::Reserved(number) => number,
+        }
+    }
+
+    /// Returns an adapter that displays external interrupts with the names `irq_names` returns
+    ///
+    /// `irq_names` is called with the IRQ number of `Exception::Interrupt`s; when it returns
+    /// `None` the interrupt is displayed as `IRQ <n>`. Other exceptions are displayed with their
+    /// architectural name.
+    pub fn display_with<'a, F>(self, irq_names: F) -> DisplayWith<F>
+    where
+        F: Fn(u16) -> Option<&'a str>,
+    {
+        DisplayWith {
+            exception: self,
+            irq_names,
+        }
+    }
+}
+
+/// Displays an `Exception` with device-specific interrupt names
+///
+/// Created by `Exception::display_with`
 #[derive(Clone, Copy, Debug)]
+pub struct DisplayWith<F> {
+    exception: Exception,
+    irq_names: F,
+}
+
+impl<'a, F> fmt::Display for DisplayWith<F>
+where
+    F: Fn(u16) -> Option<&'a str>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.exception {
+            Exception::Interrupt(irq) => match (self.irq_names)(irq) {
+                Some(name) => f.write_str(name),
+                None => self.exception.fmt(f),
+            },
+            exception => exception.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Exception::ThreadMode => f.write_str("Thread mode"),
+            Exception::Reset => f.write_str("Reset"),
+            Exception::NonMaskableInt => f.write_str("NonMaskableInt"),
+            Exception::HardFault => f.write_str("HardFault"),
+            Exception::MemoryManagement => f.write_str("MemoryManagement"),
+            Exception::BusFault => f.write_str("BusFault"),
+            Exception::UsageFault => f.write_str("UsageFault"),
+            Exception::SecureFault => f.write_str("SecureFault"),
+            Exception::SVCall => f.write_str("SVCall"),
+            Exception::DebugMonitor => f.write_str("DebugMonitor"),
+            Exception::PendSV => f.write_str("PendSV"),
+            Exception::SysTick => f.write_str("SysTick"),
+            Exception::Interrupt(irq) => write!(f, "IRQ {}", irq),
+            Exception::Reserved(number) => write!(f, "reserved exception {}", number),
+        }
+    }
+}
+
+/// Periodic PC sample packet
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub struct PeriodicPcSample {
     pub(crate) pc: Option<u32>,
 }
 
 impl PeriodicPcSample {
+    /// Creates a periodic PC sample packet
+    ///
+    /// `None` creates a "core is sleeping" sample
+    pub fn new(pc: Option<u32>) -> PeriodicPcSample {
+        PeriodicPcSample { pc }
+    }
+
     /// Returns sampled PC
     ///
     /// `None` means that the core is sleeping (`wfi` / `wfe`)
@@ -239,13 +713,29 @@ impl PeriodicPcSample {
 }
 
 /// Data trace PC packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::DataTracePcValue"))]
 pub struct DataTracePcValue {
     pub(crate) cmpn: u8,
     pub(crate) pc: u32,
 }
 
 impl DataTracePcValue {
+    /// Creates a data trace PC value packet
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `comparator` is not in the range `0..4`
+    pub fn new(comparator: u8, pc: u32) -> DataTracePcValue {
+        assert!(comparator < 4, "invalid comparator");
+
+        DataTracePcValue {
+            cmpn: comparator,
+            pc,
+        }
+    }
+
     /// Comparator that generated the data
     pub fn comparator(&self) -> u8 {
         self.cmpn
@@ -258,13 +748,29 @@ impl DataTracePcValue {
 }
 
 /// Data trace address packet
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::DataTraceAddress"))]
 pub struct DataTraceAddress {
     pub(crate) cmpn: u8,
     pub(crate) address: u16,
 }
 
 impl DataTraceAddress {
+    /// Creates a data trace address packet
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `comparator` is not in the range `0..4`
+    pub fn new(comparator: u8, address: u16) -> DataTraceAddress {
+        assert!(comparator < 4, "invalid comparator");
+
+        DataTraceAddress {
+            cmpn: comparator,
+            address,
+        }
+    }
+
     /// Data address that caused the successful address comparison
     pub fn address(&self) -> u16 {
         self.address
@@ -277,11 +783,12 @@ impl DataTraceAddress {
 }
 
 /// Data trace data value packet
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::DataTraceDataValue"))]
 pub struct DataTraceDataValue {
-    pub(crate) buffer: [u8; 4],
     pub(crate) cmpn: u8,
-    pub(crate) size: u8,
+    pub(crate) value: Payload,
     pub(crate) wnr: bool,
 }
 
@@ -296,6 +803,26 @@ impl fmt::Debug for DataTraceDataValue {
 }
 
 impl DataTraceDataValue {
+    /// Creates a data trace data value packet
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `comparator` is not in the range `0..4` or if `value` is not 1, 2 or
+    /// 4 bytes long
+    pub fn new(comparator: u8, write_access: bool, value: &[u8]) -> DataTraceDataValue {
+        assert!(comparator < 4, "invalid comparator");
+        assert!(
+            value.len() == 1 || value.len() == 2 || value.len() == 4,
+            "invalid value size"
+        );
+
+        DataTraceDataValue {
+            cmpn: comparator,
+            value: Payload::new(value),
+            wnr: write_access,
+        }
+    }
+
     /// Comparator that generated the data
     pub fn comparator(&self) -> u8 {
         self.cmpn
@@ -308,7 +835,7 @@ impl DataTraceDataValue {
 
     /// Data value that caused the successful data value comparison
     pub fn value(&self) -> &[u8] {
-        &self.buffer[..usize::from(self.size)]
+        self.value.as_slice()
     }
 
     /// Was this a write access?
@@ -316,3 +843,534 @@ impl DataTraceDataValue {
         self.wnr
     }
 }
+
+/// Data trace match packet
+///
+/// This packet is defined by the ARMv8-M architecture
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::DataTraceMatch"))]
+pub struct DataTraceMatch {
+    pub(crate) cmpn: u8,
+}
+
+impl DataTraceMatch {
+    /// Creates a data trace match packet
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `comparator` is not in the range `0..4`
+    pub fn new(comparator: u8) -> DataTraceMatch {
+        assert!(comparator < 4, "invalid comparator");
+
+        DataTraceMatch { cmpn: comparator }
+    }
+
+    /// Comparator that generated the packet
+    pub fn comparator(&self) -> u8 {
+        self.cmpn
+    }
+}
+
+/// Hardware source packet of a type that has no dedicated representation
+///
+/// These are hardware source packets whose discriminator is reserved, or whose payload size doesn't
+/// match the packet type the discriminator identifies.
+#[derive(Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "de::HardwareSource"))]
+pub struct HardwareSource {
+    pub(crate) disc: u8,
+    pub(crate) payload: Payload,
+}
+
+impl fmt::Debug for HardwareSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HardwareSource")
+            .field("disc", &self.disc)
+            .field("payload", &self.payload())
+            .finish()
+    }
+}
+
+impl HardwareSource {
+    /// Creates a hardware source packet
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `discriminator` is not in the range `0..32`, if `payload` is not 1,
+    /// 2 or 4 bytes long, or if the discriminator and payload size are those of one of the other
+    /// packet types (e.g. an event counter packet)
+    pub fn new(discriminator: u8, payload: &[u8]) -> HardwareSource {
+        assert!(discriminator < 32, "invalid discriminator");
+        assert!(
+            payload.len() == 1 || payload.len() == 2 || payload.len() == 4,
+            "invalid payload size"
+        );
+        assert!(
+            HardwareSource::is_generic(discriminator, payload.len() as u8),
+            "not a generic hardware source packet"
+        );
+
+        HardwareSource {
+            disc: discriminator,
+            payload: Payload::new(payload),
+        }
+    }
+
+    // does a hardware source packet with this discriminator and payload size parse into a
+    // `HardwareSource` packet?
+    pub(crate) fn is_generic(discriminator: u8, size: u8) -> bool {
+        let header = (discriminator << 3) | 0b100 | crate::encode_size(size);
+
+        matches!(
+            crate::Header::parse(header),
+            Ok(crate::Header::HardwareSource { .. })
+        )
+    }
+
+    /// The discriminator, which identifies the type of the packet
+    pub fn discriminator(&self) -> u8 {
+        self.disc
+    }
+
+    /// The payload of this packet
+    pub fn payload(&self) -> &[u8] {
+        self.payload.as_slice()
+    }
+}
+
+// number of payload bytes needed to encode `value` using 7-bit chunks
+fn continued_len(value: u32) -> u8 {
+    let mut len = 1;
+    while len < 4 && value >> (7 * len) != 0 {
+        len += 1;
+    }
+    len
+}
+
+/// The 1, 2 or 4 byte payload of Instrumentation and Data trace data value packets
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub(crate) struct Payload {
+    buffer: [u8; 4],
+    len: u8,
+}
+
+impl Payload {
+    pub(crate) fn new(bytes: &[u8]) -> Payload {
+        let mut buffer = [0; 4];
+        buffer[..bytes.len()].copy_from_slice(bytes);
+
+        Payload {
+            buffer,
+            len: bytes.len() as u8,
+        }
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        &self.buffer[..usize::from(self.len)]
+    }
+
+    pub(crate) fn len(&self) -> u8 {
+        self.len
+    }
+}
+
+// A payload is (de)serialized as a sequence of bytes
+#[cfg(feature = "serde")]
+impl serde::Serialize for Payload {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_seq(self.as_slice())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Payload {
+    fn deserialize<D>(deserializer: D) -> Result<Payload, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::{self, SeqAccess, Visitor};
+
+        struct PayloadVisitor;
+
+        impl<'de> Visitor<'de> for PayloadVisitor {
+            type Value = Payload;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a sequence of 1, 2 or 4 bytes")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Payload, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut buffer = [0; 4];
+                let mut len = 0;
+                while let Some(byte) = seq.next_element()? {
+                    if len == buffer.len() {
+                        return Err(de::Error::invalid_length(len + 1, &self));
+                    }
+
+                    buffer[len] = byte;
+                    len += 1;
+                }
+
+                match len {
+                    1 | 2 | 4 => Ok(Payload::new(&buffer[..len])),
+                    _ => Err(de::Error::invalid_length(len, &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_seq(PayloadVisitor)
+    }
+}
+
+// Deserialized packets are checked against the same invariants as the constructors; otherwise
+// `Packet::encode` could panic or produce bytes that don't decode back into the packet
+#[cfg(feature = "serde")]
+mod de {
+    use core::convert::TryFrom;
+
+    use serde::Deserialize;
+
+    use super::{Function, Payload};
+
+    type Result<T> = core::result::Result<T, &'static str>;
+
+    #[derive(Deserialize)]
+    pub struct Synchronization {
+        len: u8,
+    }
+
+    impl TryFrom<Synchronization> for super::Synchronization {
+        type Error = &'static str;
+
+        fn try_from(s: Synchronization) -> Result<Self> {
+            if s.len < 6 {
+                return Err("synchronization packets are at least 6 bytes long");
+            }
+
+            Ok(super::Synchronization { len: s.len })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct Instrumentation {
+        #[serde(default)]
+        page: u8,
+        payload: Payload,
+        port: u8,
+    }
+
+    impl TryFrom<Instrumentation> for super::Instrumentation {
+        type Error = &'static str;
+
+        fn try_from(i: Instrumentation) -> Result<Self> {
+            if i.port >= 32 {
+                return Err("invalid stimulus port");
+            }
+
+            if i.page >= 8 {
+                return Err("invalid stimulus port page");
+            }
+
+            Ok(super::Instrumentation {
+                page: i.page,
+                payload: i.payload,
+                port: i.port,
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct LocalTimestamp {
+        delta: u32,
+        tc: u8,
+        len: u8,
+    }
+
+    impl TryFrom<LocalTimestamp> for super::LocalTimestamp {
+        type Error = &'static str;
+
+        fn try_from(lt: LocalTimestamp) -> Result<Self> {
+            let valid = match lt.len {
+                // LTS2: a 3-bit timestamp in the header; 0 and 7 are other headers
+                1 => lt.tc == 0 && lt.delta != 0 && lt.delta < 0b111,
+                // LTS1: 7 timestamp bits per payload byte
+                2..=5 => lt.tc < 4 && u64::from(lt.delta) < 1 << (7 * (lt.len - 1)),
+                _ => false,
+            };
+
+            if !valid {
+                return Err("invalid local timestamp packet");
+            }
+
+            Ok(super::LocalTimestamp {
+                delta: lt.delta,
+                tc: lt.tc,
+                len: lt.len,
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct GTS1 {
+        bits: u32,
+        clk_ch: bool,
+        len: u8,
+        wrap: bool,
+    }
+
+    impl TryFrom<GTS1> for super::GTS1 {
+        type Error = &'static str;
+
+        fn try_from(gt: GTS1) -> Result<Self> {
+            let valid = match gt.len {
+                // the flags are in the fourth payload byte
+                2..=4 => !gt.clk_ch && !gt.wrap && gt.bits < 1 << (7 * (gt.len - 1)),
+                5 => gt.bits < 1 << 26,
+                _ => false,
+            };
+
+            if !valid {
+                return Err("invalid GTS1 packet");
+            }
+
+            Ok(super::GTS1 {
+                bits: gt.bits,
+                clk_ch: gt.clk_ch,
+                len: gt.len,
+                wrap: gt.wrap,
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct GTS2 {
+        bits: u64,
+        b64: bool,
+    }
+
+    impl TryFrom<GTS2> for super::GTS2 {
+        type Error = &'static str;
+
+        fn try_from(gt: GTS2) -> Result<Self> {
+            let width = if gt.b64 { 38 } else { 22 };
+            if gt.bits >= 1 << width {
+                return Err("too many timestamp bits");
+            }
+
+            Ok(super::GTS2 {
+                bits: gt.bits,
+                b64: gt.b64,
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct StimulusPortPage {
+        page: u8,
+    }
+
+    impl TryFrom<StimulusPortPage> for super::StimulusPortPage {
+        type Error = &'static str;
+
+        fn try_from(spp: StimulusPortPage) -> Result<Self> {
+            if spp.page >= 8 {
+                return Err("invalid stimulus port page");
+            }
+
+            Ok(super::StimulusPortPage { page: spp.page })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct EventCounter {
+        payload: u8,
+    }
+
+    impl TryFrom<EventCounter> for super::EventCounter {
+        type Error = &'static str;
+
+        fn try_from(ec: EventCounter) -> Result<Self> {
+            if ec.payload >> 6 != 0 {
+                return Err("invalid event counter payload");
+            }
+
+            Ok(super::EventCounter {
+                payload: ec.payload,
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct ExceptionTrace {
+        function: Function,
+        number: u16,
+    }
+
+    impl TryFrom<ExceptionTrace> for super::ExceptionTrace {
+        type Error = &'static str;
+
+        fn try_from(et: ExceptionTrace) -> Result<Self> {
+            if et.number >= 1 << 9 {
+                return Err("invalid exception number");
+            }
+
+            Ok(super::ExceptionTrace {
+                function: et.function,
+                number: et.number,
+            })
+        }
+    }
+
+    fn comparator(cmpn: u8) -> Result<u8> {
+        if cmpn < 4 {
+            Ok(cmpn)
+        } else {
+            Err("invalid comparator")
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub enum Exception {
+        ThreadMode,
+        Reset,
+        NonMaskableInt,
+        HardFault,
+        MemoryManagement,
+        BusFault,
+        UsageFault,
+        SecureFault,
+        SVCall,
+        DebugMonitor,
+        PendSV,
+        SysTick,
+        Interrupt(u16),
+        Reserved(u16),
+    }
+
+    impl TryFrom<Exception> for super::Exception {
+        type Error = &'static str;
+
+        fn try_from(e: Exception) -> Result<Self> {
+            // only the values that an exception number maps to
+            let number = match e {
+                Exception::ThreadMode => 0,
+                Exception::Reset => 1,
+                Exception::NonMaskableInt => 2,
+                Exception::HardFault => 3,
+                Exception::MemoryManagement => 4,
+                Exception::BusFault => 5,
+                Exception::UsageFault => 6,
+                Exception::SecureFault => 7,
+                Exception::SVCall => 11,
+                Exception::DebugMonitor => 12,
+                Exception::PendSV => 14,
+                Exception::SysTick => 15,
+                Exception::Interrupt(irq) if irq < (1 << 9) - 16 => irq + 16,
+                Exception::Interrupt(_) => return Err("invalid IRQ number"),
+                Exception::Reserved(number) => match super::Exception::from_number(number) {
+                    super::Exception::Reserved(_) => number,
+                    _ => return Err("not a reserved exception number"),
+                },
+            };
+
+            Ok(super::Exception::from_number(number))
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct DataTracePcValue {
+        cmpn: u8,
+        pc: u32,
+    }
+
+    impl TryFrom<DataTracePcValue> for super::DataTracePcValue {
+        type Error = &'static str;
+
+        fn try_from(dtpv: DataTracePcValue) -> Result<Self> {
+            Ok(super::DataTracePcValue {
+                cmpn: comparator(dtpv.cmpn)?,
+                pc: dtpv.pc,
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct DataTraceAddress {
+        cmpn: u8,
+        address: u16,
+    }
+
+    impl TryFrom<DataTraceAddress> for super::DataTraceAddress {
+        type Error = &'static str;
+
+        fn try_from(dta: DataTraceAddress) -> Result<Self> {
+            Ok(super::DataTraceAddress {
+                cmpn: comparator(dta.cmpn)?,
+                address: dta.address,
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct DataTraceDataValue {
+        cmpn: u8,
+        value: Payload,
+        wnr: bool,
+    }
+
+    impl TryFrom<DataTraceDataValue> for super::DataTraceDataValue {
+        type Error = &'static str;
+
+        fn try_from(dtdv: DataTraceDataValue) -> Result<Self> {
+            Ok(super::DataTraceDataValue {
+                cmpn: comparator(dtdv.cmpn)?,
+                value: dtdv.value,
+                wnr: dtdv.wnr,
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct DataTraceMatch {
+        cmpn: u8,
+    }
+
+    impl TryFrom<DataTraceMatch> for super::DataTraceMatch {
+        type Error = &'static str;
+
+        fn try_from(dtm: DataTraceMatch) -> Result<Self> {
+            Ok(super::DataTraceMatch {
+                cmpn: comparator(dtm.cmpn)?,
+            })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct HardwareSource {
+        disc: u8,
+        payload: Payload,
+    }
+
+    impl TryFrom<HardwareSource> for super::HardwareSource {
+        type Error = &'static str;
+
+        fn try_from(hs: HardwareSource) -> Result<Self> {
+            if hs.disc >= 32 || !super::HardwareSource::is_generic(hs.disc, hs.payload.len()) {
+                return Err("not a generic hardware source packet");
+            }
+
+            Ok(super::HardwareSource {
+                disc: hs.disc,
+                payload: hs.payload,
+            })
+        }
+    }
+}
@@ -0,0 +1,405 @@
+//! SWO line decoding
+//!
+//! The Serial Wire Output (SWO) pin carries the trace data as an asynchronous serial signal,
+//! using either the NRZ (`Nrz`) or the Manchester (`Manchester`) encoding, as selected by the
+//! `TPIU_SPPR` register. This module turns logic analyzer samples of that pin back into bytes,
+//! which can then be fed into a `Decoder` (or a `tpiu::Deframer` if the TPIU formatter is
+//! enabled).
+//!
+//! ```
+//! use itm::swo::Nrz;
+//!
+//! // 8 samples per bit: 1 MHz sample rate, 125 kbps line
+//! let mut nrz = Nrz::new(1_000_000, 125_000);
+//!
+//! // idle, start bit, 0x41 (LSB first), stop bit
+//! let bits = [1, 0, 1, 0, 0, 0, 0, 0, 1, 0, 1];
+//! let samples = bits.iter().flat_map(|&bit| [bit == 1; 8]);
+//!
+//! let mut bytes = vec![];
+//! nrz.feed(samples, |byte| bytes.push(byte));
+//! assert_eq!(bytes, b"A");
+//! ```
+
+use core::convert::TryFrom;
+
+/// Decodes the NRZ (UART-like) SWO line encoding
+///
+/// Each byte is sent as a low start bit, 8 data bits, LSB first, and a high stop bit; the line
+/// idles high. Samples are taken at a fixed `sample_rate`. Bits are read at the middle of their
+/// bit period, counted from the falling edge of the start bit, so the sample rate must be at
+/// least a few times the baud rate; 8 or more samples per bit is recommended.
+///
+/// `Nrz` doesn't allocate and is available without the `std` feature.
+#[derive(Clone, Debug)]
+pub struct Nrz {
+    baud_rate: u32,
+    // number of bytes whose stop bit was not high
+    framing_errors: u64,
+    // level of the previous sample
+    level: bool,
+    sample_rate: u32,
+    state: State,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum State {
+    // waiting for the line to go idle (high)
+    Unknown,
+    // waiting for a start bit
+    Idle,
+    // receiving a byte
+    Frame {
+        // index of the next bit to sample: 0 is the start bit, 9 the stop bit
+        bit: u32,
+        byte: u8,
+        // samples since the falling edge of the start bit
+        t: u64,
+    },
+}
+
+impl Nrz {
+    /// Creates a decoder for samples taken at `sample_rate` (in Hz) of a line running at
+    /// `baud_rate` (in bit/s)
+    ///
+    /// The decoder ignores the input until the line is high (idle).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `baud_rate` is zero or greater than half the `sample_rate`
+    pub fn new(sample_rate: u32, baud_rate: u32) -> Nrz {
+        assert!(
+            baud_rate != 0 && u64::from(baud_rate) * 2 <= u64::from(sample_rate),
+            "the sample rate must be at least twice the baud rate"
+        );
+
+        Nrz {
+            baud_rate,
+            framing_errors: 0,
+            level: false,
+            sample_rate,
+            state: State::Unknown,
+        }
+    }
+
+    /// Pushes line samples (`true` is high) into the decoder and calls `f` with the bytes they
+    /// complete
+    ///
+    /// Bytes whose stop bit is not high are discarded and counted in `framing_errors`.
+    pub fn feed(&mut self, samples: impl IntoIterator<Item = bool>, mut f: impl FnMut(u8)) {
+        for level in samples {
+            self.state = match self.state {
+                State::Unknown if level => State::Idle,
+                State::Unknown => State::Unknown,
+                // falling edge of the start bit
+                State::Idle if self.level && !level => State::Frame {
+                    bit: 0,
+                    byte: 0,
+                    t: 0,
+                },
+                State::Idle => State::Idle,
+                State::Frame { bit, byte, t } => self.sample(bit, byte, t + 1, level, &mut f),
+            };
+
+            self.level = level;
+        }
+    }
+
+    // `t` samples into a frame
+    fn sample(&mut self, bit: u32, byte: u8, t: u64, level: bool, f: &mut impl FnMut(u8)) -> State {
+        // is this sample past the middle of bit `bit`?
+        let middle = 2 * t * u64::from(self.baud_rate)
+            >= (2 * u64::from(bit) + 1) * u64::from(self.sample_rate);
+
+        if !middle {
+            return State::Frame { bit, byte, t };
+        }
+
+        match bit {
+            // a glitch, not a start bit
+            0 if level => State::Idle,
+            0 => State::Frame { bit: 1, byte, t },
+            1..=8 => State::Frame {
+                bit: bit + 1,
+                byte: byte | (u8::from(level) << (bit - 1)),
+                t,
+            },
+            _ => {
+                if level {
+                    f(byte);
+                    State::Idle
+                } else {
+                    // wait for the line to go idle before looking for the next start bit
+                    self.framing_errors += 1;
+                    State::Unknown
+                }
+            }
+        }
+    }
+
+    /// Number of bytes that were discarded because their stop bit was not high
+    ///
+    /// Framing errors usually mean that the baud rate is wrong
+    pub fn framing_errors(&self) -> u64 {
+        self.framing_errors
+    }
+}
+
+/// Estimates the baud rate of an NRZ SWO line from samples taken at `sample_rate` (in Hz)
+///
+/// The estimate comes from the duration of the runs of equal samples: the shortest ones are a
+/// single bit period and the others are close to a multiple of it. The result is only as
+/// precise as the capture is long and is best rounded to a baud rate the target can produce.
+/// Returns `None` if the samples don't contain a complete run.
+///
+/// Glitches shorter than a bit period throw off the estimate so the capture should be clean;
+/// sync packets or any other trace data are fine.
+///
+/// ```
+/// use itm::swo::{self, Nrz};
+///
+/// // 8 samples per bit: 1 MHz sample rate, 125 kbps line
+/// // idle, start bit, 0x55 (LSB first), stop bit
+/// let bits = [1, 0, 1, 0, 1, 0, 1, 0, 1, 0, 1];
+/// let samples = bits.iter().flat_map(|&bit| [bit == 1; 8]).collect::<Vec<_>>();
+///
+/// let baud_rate = swo::detect_baud_rate(1_000_000, &samples).unwrap();
+/// assert_eq!(baud_rate, 125_000);
+///
+/// let mut nrz = Nrz::new(1_000_000, baud_rate);
+/// ```
+pub fn detect_baud_rate(sample_rate: u32, samples: &[bool]) -> Option<u32> {
+    let shortest = runs(samples).min()?;
+
+    // first estimate: the average of the runs that are a single bit period
+    let (mut len, mut bits) = (0, 0);
+    for run in runs(samples).filter(|&run| 2 * run < 3 * shortest) {
+        len += run;
+        bits += 1;
+    }
+
+    // refine it with the runs that are part of a frame; a frame has at most 10 equal bits (a
+    // stop bit followed by idle time can be arbitrarily long)
+    let (single_len, single_bits) = (len, bits);
+    len = 0;
+    bits = 0;
+    for run in runs(samples) {
+        // `run` divided by the first estimate, rounded
+        let n = (2 * run * single_bits + single_len) / (2 * single_len);
+        if n <= 10 {
+            len += run;
+            bits += n;
+        }
+    }
+
+    let baud_rate = (2 * u64::from(sample_rate) * bits + len) / (2 * len);
+    u32::try_from(baud_rate).ok()
+}
+
+// the lengths of the complete runs of equal samples; the first and last runs may have started
+// before, or continue after, the capture so they are not reported
+fn runs(samples: &[bool]) -> impl Iterator<Item = u64> + '_ {
+    let edges = samples.windows(2).enumerate().filter(|(_, w)| w[0] != w[1]);
+    edges
+        .clone()
+        .zip(edges.skip(1))
+        .map(|((start, _), (end, _))| (end - start) as u64)
+}
+
+/// Decodes the Manchester SWO line encoding
+///
+/// Every bit period has a transition in its middle: a 1 is sent as high then low and a 0 as low
+/// then high. The line idles low. A packet starts with a 1 (the start bit), continues with data
+/// bytes, sent LSB first, and ends when the line stays idle for a bit period. Each mid-bit
+/// transition resynchronizes the decoder so the baud rate only needs to be approximately right;
+/// the sample rate must be at least 4 times the baud rate.
+///
+/// `Manchester` doesn't allocate and is available without the `std` feature.
+///
+/// ```
+/// use itm::swo::Manchester;
+///
+/// // 8 samples per bit: 1 MHz sample rate, 125 kbps line
+/// let mut manchester = Manchester::new(1_000_000, 125_000);
+///
+/// // idle, start bit, 0x41 (LSB first), idle
+/// let mut samples = vec![false; 8];
+/// for bit in [true, true, false, false, false, false, false, true, false] {
+///     samples.extend([bit; 4]);
+///     samples.extend([!bit; 4]);
+/// }
+/// samples.extend([false; 8]);
+///
+/// let mut bytes = vec![];
+/// manchester.feed(samples, |byte| bytes.push(byte));
+/// assert_eq!(bytes, b"A");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Manchester {
+    baud_rate: u32,
+    // number of packets that ended in the middle of a byte
+    framing_errors: u64,
+    // level of the previous sample
+    level: bool,
+    sample_rate: u32,
+    state: ManchesterState,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ManchesterState {
+    // waiting for the line to go idle (low)
+    Unknown,
+    // waiting for a start bit
+    Idle,
+    // in the first half of the start bit
+    Start {
+        // samples since the start of the start bit
+        t: u64,
+    },
+    // receiving data bits
+    Data {
+        // number of bits in `byte`
+        bits: u32,
+        byte: u8,
+        // level sampled in the first half of the current bit period
+        first_half: Option<bool>,
+        // samples since the previous mid-bit transition
+        t: u64,
+    },
+}
+
+impl Manchester {
+    /// Creates a decoder for samples taken at `sample_rate` (in Hz) of a line running at
+    /// `baud_rate` (in bit/s)
+    ///
+    /// The decoder ignores the input until the line is low (idle).
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `baud_rate` is zero or greater than a quarter of the
+    /// `sample_rate`
+    pub fn new(sample_rate: u32, baud_rate: u32) -> Manchester {
+        assert!(
+            baud_rate != 0 && u64::from(baud_rate) * 4 <= u64::from(sample_rate),
+            "the sample rate must be at least 4 times the baud rate"
+        );
+
+        Manchester {
+            baud_rate,
+            framing_errors: 0,
+            level: true,
+            sample_rate,
+            state: ManchesterState::Unknown,
+        }
+    }
+
+    /// Pushes line samples (`true` is high) into the decoder and calls `f` with the bytes they
+    /// complete
+    ///
+    /// A packet that ends in the middle of a byte is counted in `framing_errors`; its last,
+    /// incomplete, byte is discarded.
+    pub fn feed(&mut self, samples: impl IntoIterator<Item = bool>, mut f: impl FnMut(u8)) {
+        for level in samples {
+            self.state = self.next_state(level, &mut f);
+            self.level = level;
+        }
+    }
+
+    fn next_state(&mut self, level: bool, f: &mut impl FnMut(u8)) -> ManchesterState {
+        match self.state {
+            ManchesterState::Unknown if level => ManchesterState::Unknown,
+            ManchesterState::Unknown => ManchesterState::Idle,
+            // rising edge of the start bit
+            ManchesterState::Idle if level && !self.level => ManchesterState::Start { t: 0 },
+            ManchesterState::Idle => ManchesterState::Idle,
+            // the middle of the start bit
+            ManchesterState::Start { .. } if !level => ManchesterState::Data {
+                bits: 0,
+                byte: 0,
+                first_half: None,
+                t: 0,
+            },
+            ManchesterState::Start { t } => {
+                if self.periods(t + 1, 4) {
+                    // high for longer than a bit period; this is not Manchester data
+                    ManchesterState::Unknown
+                } else {
+                    ManchesterState::Start { t: t + 1 }
+                }
+            }
+            ManchesterState::Data {
+                mut bits,
+                mut byte,
+                first_half,
+                t,
+            } => {
+                let t = t + 1;
+
+                match first_half {
+                    // sample the first half of the bit period at 3/4 of a period from the
+                    // previous mid-bit transition
+                    None if self.periods(t, 3) => ManchesterState::Data {
+                        bits,
+                        byte,
+                        first_half: Some(level),
+                        t,
+                    },
+                    None => ManchesterState::Data {
+                        bits,
+                        byte,
+                        first_half,
+                        t,
+                    },
+                    // mid-bit transition
+                    Some(first_half) if level != first_half => {
+                        byte |= u8::from(first_half) << bits;
+                        bits += 1;
+
+                        if bits == 8 {
+                            f(byte);
+                            bits = 0;
+                            byte = 0;
+                        }
+
+                        ManchesterState::Data {
+                            bits,
+                            byte,
+                            first_half: None,
+                            t: 0,
+                        }
+                    }
+                    // no transition by 5/4 of a period: end of the packet
+                    Some(_) if self.periods(t, 5) => {
+                        if bits != 0 {
+                            self.framing_errors += 1;
+                        }
+
+                        if level {
+                            ManchesterState::Unknown
+                        } else {
+                            ManchesterState::Idle
+                        }
+                    }
+                    Some(_) => ManchesterState::Data {
+                        bits,
+                        byte,
+                        first_half,
+                        t,
+                    },
+                }
+            }
+        }
+    }
+
+    // are `t` samples at least `quarters` quarters of a bit period?
+    fn periods(&self, t: u64, quarters: u64) -> bool {
+        4 * t * u64::from(self.baud_rate) >= quarters * u64::from(self.sample_rate)
+    }
+
+    /// Number of packets that ended in the middle of a byte
+    ///
+    /// Framing errors usually mean that the baud rate is wrong or that the capture is noisy
+    pub fn framing_errors(&self) -> u64 {
+        self.framing_errors
+    }
+}
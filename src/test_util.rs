@@ -0,0 +1,166 @@
+//! `proptest` strategies that generate ITM packets and streams
+//!
+//! Only packets that can appear in an ITM stream are generated.
+//!
+//! ```
+//! use itm::{packet::Kind, test_util, SliceDecoder};
+//! use proptest::prelude::*;
+//!
+//! proptest!(|((packets, bytes) in test_util::stream(
+//!     test_util::weighted_packet(&[(8, Kind::Instrumentation), (1, Kind::LocalTimestamp)]),
+//!     0..64,
+//! ))| {
+//!     let decoded = SliceDecoder::new(&bytes).collect::<Result<Vec<_>, _>>().unwrap();
+//!     prop_assert_eq!(decoded, packets);
+//! });
+//! ```
+
+use proptest::{
+    collection::{self, SizeRange},
+    prelude::*,
+    strategy::Union,
+};
+
+use crate::{
+    packet::{
+        DataTraceAddress, DataTraceDataValue, DataTraceMatch, DataTracePcValue, EventCounter,
+        ExceptionTrace, Function, HardwareSource, Instrumentation, Kind, LocalTimestamp,
+        PeriodicPcSample, StimulusPortPage, Synchronization, GTS1, GTS2,
+    },
+    Packet,
+};
+
+const KINDS: [Kind; 15] = [
+    Kind::Overflow,
+    Kind::Synchronization,
+    Kind::Instrumentation,
+    Kind::LocalTimestamp,
+    Kind::GTS1,
+    Kind::GTS2,
+    Kind::StimulusPortPage,
+    Kind::EventCounter,
+    Kind::ExceptionTrace,
+    Kind::PeriodicPcSample,
+    Kind::DataTracePcValue,
+    Kind::DataTraceAddress,
+    Kind::DataTraceDataValue,
+    Kind::DataTraceMatch,
+    Kind::HardwareSource,
+];
+
+// a 1, 2 or 4 byte payload
+fn payload() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        collection::vec(any::<u8>(), 1),
+        collection::vec(any::<u8>(), 2),
+        collection::vec(any::<u8>(), 4),
+    ]
+}
+
+/// Generates packets of the given `kind`
+pub fn packet_of_kind(kind: Kind) -> BoxedStrategy<Packet> {
+    match kind {
+        Kind::Overflow => Just(Packet::Overflow).boxed(),
+        Kind::Synchronization => (6..=u8::MAX)
+            .prop_map(|len| Synchronization::new(len).into())
+            .boxed(),
+        Kind::Instrumentation => (0..32u8, payload())
+            .prop_map(|(port, payload)| Instrumentation::new(port, &payload).into())
+            .boxed(),
+        Kind::LocalTimestamp => (0..1u32 << 28, any::<bool>(), any::<bool>())
+            .prop_map(|(delta, ts, ev)| LocalTimestamp::new(delta, ts, ev).into())
+            .boxed(),
+        Kind::GTS1 => (0..1u32 << 26, any::<bool>(), any::<bool>())
+            .prop_map(|(bits, clk, wrap)| GTS1::new(bits, clk, wrap).into())
+            .boxed(),
+        Kind::GTS2 => prop_oneof![
+            (0..1u64 << 22).prop_map(|bits| GTS2::new(bits, false).into()),
+            (0..1u64 << 38).prop_map(|bits| GTS2::new(bits, true).into()),
+        ]
+        .boxed(),
+        Kind::StimulusPortPage => (0..8u8)
+            .prop_map(|page| StimulusPortPage::new(page).into())
+            .boxed(),
+        Kind::EventCounter => any::<[bool; 6]>()
+            .prop_map(|[cpi, exc, sleep, lsu, fold, post]| {
+                EventCounter::new(cpi, exc, sleep, lsu, fold, post).into()
+            })
+            .boxed(),
+        Kind::ExceptionTrace => (
+            0..1u16 << 9,
+            prop_oneof![
+                Just(Function::Enter),
+                Just(Function::Exit),
+                Just(Function::Return)
+            ],
+        )
+            .prop_map(|(number, function)| ExceptionTrace::new(number, function).into())
+            .boxed(),
+        Kind::PeriodicPcSample => any::<Option<u32>>()
+            .prop_map(|pc| PeriodicPcSample::new(pc).into())
+            .boxed(),
+        Kind::DataTracePcValue => (0..4u8, any::<u32>())
+            .prop_map(|(cmpn, pc)| DataTracePcValue::new(cmpn, pc).into())
+            .boxed(),
+        Kind::DataTraceAddress => (0..4u8, any::<u16>())
+            .prop_map(|(cmpn, address)| DataTraceAddress::new(cmpn, address).into())
+            .boxed(),
+        Kind::DataTraceDataValue => (0..4u8, any::<bool>(), payload())
+            .prop_map(|(cmpn, wnr, value)| DataTraceDataValue::new(cmpn, wnr, &value).into())
+            .boxed(),
+        Kind::DataTraceMatch => (0..4u8)
+            .prop_map(|cmpn| DataTraceMatch::new(cmpn).into())
+            .boxed(),
+        Kind::HardwareSource => (0..32u8, payload())
+            .prop_filter("not a generic hardware source packet", |(disc, payload)| {
+                HardwareSource::is_generic(*disc, payload.len() as u8)
+            })
+            .prop_map(|(disc, payload)| HardwareSource::new(disc, &payload).into())
+            .boxed(),
+    }
+}
+
+/// Generates packets of any kind; all kinds are equally likely
+pub fn packet() -> BoxedStrategy<Packet> {
+    weighted_packet(&KINDS.map(|kind| (1, kind)))
+}
+
+/// Generates packets whose kinds are picked according to the given weights
+///
+/// # Panics
+///
+/// This function panics if all the weights are zero
+pub fn weighted_packet(mix: &[(u32, Kind)]) -> BoxedStrategy<Packet> {
+    let options = mix
+        .iter()
+        .filter(|&&(weight, _)| weight != 0)
+        .map(|&(weight, kind)| (weight, packet_of_kind(kind)))
+        .collect::<Vec<_>>();
+    assert!(!options.is_empty(), "no packet kind to generate");
+
+    Union::new_weighted(options).boxed()
+}
+
+/// Generates ITM streams of `len` packets
+///
+/// The value is the sequence of packets, as a decoder would yield them, and the stream bytes they
+/// encode into. Instrumentation packets are on the stimulus port page selected by the Stimulus
+/// Port Page packets that precede them.
+pub fn stream(
+    packet: impl Strategy<Value = Packet>,
+    len: impl Into<SizeRange>,
+) -> impl Strategy<Value = (Vec<Packet>, Vec<u8>)> {
+    collection::vec(packet, len).prop_map(|mut packets| {
+        let mut page = 0;
+        for packet in &mut packets {
+            match packet {
+                Packet::StimulusPortPage(spp) => page = spp.page(),
+                Packet::Instrumentation(i) => *i = i.with_page(page),
+                _ => {}
+            }
+        }
+
+        let bytes = packets.iter().flat_map(Packet::to_bytes).collect();
+        (packets, bytes)
+    })
+}
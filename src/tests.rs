@@ -1,6 +1,17 @@
-use std::io::Cursor;
-
-use crate::{packet::Function, Error, Packet, Stream};
+use std::{io::Cursor, time::Duration};
+
+use crate::{
+    packet::{
+        DataTraceAddress, DataTraceDataValue, DataTraceMatch, DataTracePcValue, EventCounter,
+        Exception, ExceptionTrace, Function, HardwareSource, Instrumentation, Kind, LocalTimestamp,
+        PeriodicPcSample, Source, StimulusPortPage, Synchronization, GTS1, GTS2,
+    },
+    parse_one,
+    swo::{self, Manchester, Nrz},
+    timeline::{Clock, Timeline},
+    tpiu::{self, Deframer, Format, Framer},
+    Architecture, Decoder, Error, HeaderPolicy, Packet, PacketVisitor, SliceDecoder, Stream,
+};
 
 #[test]
 fn synchronization() {
@@ -40,7 +51,7 @@ fn synchronization() {
 
     // next byte should be a non-zero byte
     match stream.next().unwrap() {
-        Some(Err(Error::MalformedPacket { header, len })) => {
+        Some(Err(Error::Truncated { header, len })) => {
             assert_eq!(header, 1);
             assert_eq!(len, 1);
         }
@@ -512,3 +523,1802 @@ fn data_trace_data_value() {
     // EOF
     assert!(stream.next().unwrap().is_none());
 }
+
+#[test]
+fn decoder() {
+    let mut decoder = Decoder::new();
+
+    // nothing to decode yet
+    assert!(decoder.next().is_none());
+
+    // Instrumentation packet split across two chunks
+    decoder.feed(&[0x03, 0x10]);
+    assert!(decoder.next().is_none());
+
+    decoder.feed(&[0x20, 0x30, 0x40, 0x70]);
+    match decoder.next().unwrap().unwrap() {
+        Packet::Instrumentation(i) => {
+            assert_eq!(i.port(), 0);
+            assert_eq!(i.payload(), &[0x10, 0x20, 0x30, 0x40]);
+        }
+        _ => panic!(),
+    }
+
+    match decoder.next().unwrap().unwrap() {
+        Packet::Overflow => {}
+        _ => panic!(),
+    }
+
+    assert!(decoder.next().is_none());
+
+    // truncated packet
+    decoder.feed(&[0x17, 0x00]);
+    assert!(decoder.next().is_none());
+
+    match decoder.finish() {
+        Some(Error::Truncated { header, len }) => {
+            assert_eq!(header, 0x17);
+            assert_eq!(len, 2);
+        }
+        _ => panic!(),
+    }
+
+    assert!(decoder.finish().is_none());
+}
+
+#[test]
+fn long_zero_run() {
+    let mut decoder = Decoder::new();
+
+    decoder.feed(&[0; 300]);
+
+    match decoder.next().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => {
+            assert_eq!(header, 0);
+            assert_eq!(len, 254);
+        }
+        _ => panic!(),
+    }
+
+    assert!(decoder.next().is_none());
+}
+
+#[test]
+fn long_gts2() {
+    let mut decoder = Decoder::new();
+
+    // the 6th payload byte of a GTS2 packet must have its C bit cleared
+    decoder.feed(&[
+        0xb4, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    ]);
+
+    match decoder.next().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => {
+            assert_eq!(header, 0xb4);
+            assert_eq!(len, 6);
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn packets() {
+    let stream = Stream::new(
+        Cursor::new(&[
+            // Overflow
+            0x70, //
+            // Instrumentation
+            0x01, 0x10, //
+            // truncated Instrumentation
+            0x02, 0x20,
+        ]),
+        false,
+    );
+
+    let packets = stream.into_iter().collect::<Vec<_>>();
+    assert_eq!(packets.len(), 3);
+
+    match packets[0] {
+        Ok(Packet::Overflow) => {}
+        _ => panic!(),
+    }
+
+    match packets[1] {
+        Ok(Packet::Instrumentation(i)) => assert_eq!(i.payload(), &[0x10]),
+        _ => panic!(),
+    }
+
+    match packets[2] {
+        Err(Error::Truncated { header, len }) => {
+            assert_eq!(header, 0x02);
+            assert_eq!(len, 2);
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn decode_into() {
+    let bytes = [0x70, 0x04, 0x01, 0x10, 0x70];
+
+    let mut out = [const { None }; 2];
+    let mut decoder = SliceDecoder::new(&bytes);
+
+    // `out` is full
+    assert_eq!(decoder.decode_into(&mut out), 2);
+    assert!(matches!(out[0], Some(Ok(Packet::Overflow))));
+    assert!(matches!(
+        out[1],
+        Some(Err(Error::ReservedHeader { byte: 0x04 }))
+    ));
+
+    assert_eq!(decoder.decode_into(&mut out), 2);
+    assert!(matches!(out[0], Some(Ok(Packet::Instrumentation(_)))));
+    assert!(matches!(out[1], Some(Ok(Packet::Overflow))));
+
+    // no more packets
+    assert_eq!(decoder.decode_into(&mut out), 0);
+}
+
+#[test]
+fn slice_decoder() {
+    let mut decoder = SliceDecoder::new(&[
+        // Overflow
+        0x70, //
+        // Reserved header
+        0x04, //
+        // incomplete Exception Trace
+        0x0e, 0x10,
+    ]);
+
+    match decoder.next().unwrap() {
+        Ok(Packet::Overflow) => {}
+        _ => panic!(),
+    }
+
+    match decoder.next().unwrap() {
+        Err(Error::ReservedHeader { byte }) => assert_eq!(byte, 0x04),
+        _ => panic!(),
+    }
+
+    assert!(decoder.next().is_none());
+    assert_eq!(decoder.remainder(), &[0x0e, 0x10]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde() {
+    let packet = SliceDecoder::new(&[0x02, 0x10, 0x20])
+        .next()
+        .unwrap()
+        .unwrap();
+
+    let json = serde_json::to_string(&packet).unwrap();
+    assert_eq!(
+        json,
+        r#"{"Instrumentation":{"page":0,"payload":[16,32],"port":0}}"#
+    );
+
+    match serde_json::from_str(&json).unwrap() {
+        Packet::Instrumentation(i) => {
+            assert_eq!(i.port(), 0);
+            assert_eq!(i.payload(), &[0x10, 0x20]);
+        }
+        _ => panic!(),
+    }
+
+    // payloads can only be 1, 2 or 4 bytes long
+    assert!(
+        serde_json::from_str::<Packet>(r#"{"Instrumentation":{"payload":[1,2,3],"port":0}}"#)
+            .is_err()
+    );
+}
+
+#[test]
+fn kind() {
+    let kinds = SliceDecoder::new(&[
+        // Overflow
+        0x70, //
+        // Instrumentation
+        0x01, 0x10, //
+        // LTS2
+        0x40, //
+        // Exception Trace
+        0x0e, 0x10, 0x10,
+    ])
+    .map(|p| p.unwrap().kind())
+    .collect::<Vec<_>>();
+
+    assert_eq!(
+        kinds,
+        [
+            Kind::Overflow,
+            Kind::Instrumentation,
+            Kind::LocalTimestamp,
+            Kind::ExceptionTrace
+        ]
+    );
+}
+
+#[test]
+fn encode() {
+    let packets = [
+        Packet::Overflow,
+        Packet::Synchronization(Synchronization::new(6)),
+        Packet::Synchronization(Synchronization::new(12)),
+        Packet::Instrumentation(Instrumentation::new(1, &[0x30, 0x20])),
+        Packet::Instrumentation(Instrumentation::new(31, &[0x70, 0x60, 0x50, 0x40])),
+        Packet::LocalTimestamp(LocalTimestamp::new(4, false, false)),
+        Packet::LocalTimestamp(LocalTimestamp::new(0, false, false)),
+        Packet::LocalTimestamp(LocalTimestamp::new(1 << 27, true, true)),
+        Packet::GTS1(GTS1::new(0x7f, false, false)),
+        Packet::GTS1(GTS1::new(0x7f, true, false)),
+        Packet::GTS1(GTS1::new((1 << 26) - 1, false, true)),
+        Packet::GTS2(GTS2::new((1 << 22) - 1, false)),
+        Packet::GTS2(GTS2::new((1 << 38) - 1, true)),
+        Packet::StimulusPortPage(StimulusPortPage::new(7)),
+        Packet::EventCounter(EventCounter::new(true, false, true, false, true, false)),
+        Packet::ExceptionTrace(ExceptionTrace::new(0x10, Function::Enter)),
+        Packet::ExceptionTrace(ExceptionTrace::new(0x1ff, Function::Exit)),
+        Packet::ExceptionTrace(ExceptionTrace::new(0, Function::Return)),
+        Packet::PeriodicPcSample(PeriodicPcSample::new(None)),
+        Packet::PeriodicPcSample(PeriodicPcSample::new(Some(0x0800_0000))),
+        Packet::DataTracePcValue(DataTracePcValue::new(3, 0x0800_0400)),
+        Packet::DataTraceAddress(DataTraceAddress::new(2, 0x3412)),
+        Packet::DataTraceDataValue(DataTraceDataValue::new(1, true, &[0x12, 0x34])),
+        Packet::DataTraceDataValue(DataTraceDataValue::new(0, false, &[0x12])),
+    ];
+
+    let bytes = packets
+        .iter()
+        .flat_map(|p| p.to_bytes())
+        .collect::<Vec<_>>();
+
+    let decoded = SliceDecoder::new(&bytes)
+        .map(|p| p.unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(decoded, packets);
+
+    // same encoding as the one used in the `instrumentation` test
+    assert_eq!(
+        Packet::Instrumentation(Instrumentation::new(1, &[0x30, 0x20])).to_bytes(),
+        [0x0a, 0x30, 0x20]
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn async_stream() {
+    use crate::AsyncStream;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+
+    runtime.block_on(async {
+        let mut stream = AsyncStream::new(
+            &[
+                // Instrumentation
+                0x01, 0x10, //
+                // truncated Instrumentation
+                0x02, 0x20,
+            ][..],
+        );
+
+        match stream.next().await.unwrap().unwrap().unwrap() {
+            Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x10]),
+            _ => panic!(),
+        }
+
+        match stream.next().await.unwrap().unwrap() {
+            Err(Error::Truncated { header, len }) => {
+                assert_eq!(header, 0x02);
+                assert_eq!(len, 2);
+            }
+            _ => panic!(),
+        }
+
+        // EOF
+        assert!(stream.next().await.unwrap().is_none());
+    });
+}
+
+#[test]
+fn ignore() {
+    let mut decoder = Decoder::builder()
+        .ignore(Kind::Synchronization)
+        .ignore(Kind::LocalTimestamp)
+        .build();
+
+    decoder.feed(&[
+        // Synchronization
+        0, 0, 0, 0, 0, 0x80, //
+        // Instrumentation
+        0x01, 0x10, //
+        // LTS2
+        0x40, //
+        // Overflow
+        0x70,
+    ]);
+
+    match decoder.next().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x10]),
+        _ => panic!(),
+    }
+
+    match decoder.next().unwrap().unwrap() {
+        Packet::Overflow => {}
+        _ => panic!(),
+    }
+
+    assert!(decoder.next().is_none());
+}
+
+#[test]
+fn visitor() {
+    #[derive(Default)]
+    struct Console {
+        errors: usize,
+        output: Vec<u8>,
+        overflows: usize,
+    }
+
+    impl PacketVisitor for Console {
+        fn on_overflow(&mut self) {
+            self.overflows += 1;
+        }
+
+        fn on_instrumentation(&mut self, packet: Instrumentation) {
+            self.output.extend_from_slice(packet.payload());
+        }
+
+        fn on_error(&mut self, _: Error) {
+            self.errors += 1;
+        }
+    }
+
+    let mut decoder = Decoder::new();
+    decoder.feed(&[
+        // Instrumentation
+        0x02, b'H', b'i', //
+        // LTS2 (ignored by the visitor)
+        0x40, //
+        // Overflow
+        0x70, //
+        // Reserved header
+        0x04, //
+        // Instrumentation
+        0x01, b'!',
+    ]);
+
+    let mut console = Console::default();
+    decoder.visit(&mut console);
+
+    assert_eq!(console.output, b"Hi!");
+    assert_eq!(console.overflows, 1);
+    assert_eq!(console.errors, 1);
+}
+
+#[test]
+fn stats() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // Instrumentation
+            0x01, 0x10, //
+            // Overflow
+            0x70, //
+            // Reserved header
+            0x04, //
+            // Instrumentation
+            0x02, 0x10, 0x20, //
+            // truncated Exception Trace
+            0x0e, 0x10,
+        ]),
+        false,
+    );
+
+    while stream.next().unwrap().is_some() {}
+
+    let stats = stream.decoder().stats();
+    assert_eq!(stats.bytes(), 9);
+    assert_eq!(stats.packets(Kind::Instrumentation), 2);
+    assert_eq!(stats.total_packets(), 3);
+    assert_eq!(stats.overflows(), 1);
+    assert_eq!(stats.reserved_headers(), 1);
+    assert_eq!(stats.malformed_packets(), 0);
+    assert_eq!(stats.truncated_packets(), 1);
+}
+
+#[test]
+fn offset() {
+    let mut decoder = Decoder::new();
+
+    decoder.feed(&[
+        // Instrumentation
+        0x02, 0x10, 0x20, //
+        // Reserved header
+        0x04,
+    ]);
+
+    let packet = decoder.next().unwrap().unwrap();
+    assert_eq!((decoder.offset(), packet.len()), (0, 3));
+
+    let error = decoder.next().unwrap().unwrap_err();
+    assert_eq!((decoder.offset(), error.len()), (3, 1));
+
+    assert!(decoder.next().is_none());
+
+    decoder.feed(&[
+        // Overflow
+        0x70, //
+        // truncated Instrumentation
+        0x03, 0x10,
+    ]);
+
+    decoder.next().unwrap().unwrap();
+    assert_eq!(decoder.offset(), 4);
+
+    assert!(decoder.next().is_none());
+
+    let error = decoder.finish().unwrap();
+    assert_eq!((decoder.offset(), error.len()), (5, 2));
+}
+
+#[test]
+fn round_trip() {
+    // simple linear congruential generator; good enough to produce varied payloads
+    let mut seed = 0x1234_5678u32;
+    let mut random = move || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 16) as u8
+    };
+
+    for header in 0..=255 {
+        for i in 0..64 {
+            let mut input = [0; 8];
+            input[0] = header;
+            for byte in &mut input[1..] {
+                *byte = match i {
+                    0 => 0x00,
+                    1 => 0xff,
+                    // clear the C (Continue) bits every now and then
+                    _ if i % 4 == 0 => random() & 0x7f,
+                    _ => random(),
+                };
+            }
+
+            if let Some(Ok(packet)) = SliceDecoder::new(&input).next() {
+                let len = usize::from(packet.len());
+
+                assert_eq!(packet.to_bytes(), &input[..len], "{:?}", packet);
+                assert_eq!(
+                    SliceDecoder::new(&packet.to_bytes())
+                        .next()
+                        .unwrap()
+                        .unwrap(),
+                    packet
+                );
+            }
+        }
+    }
+
+    assert_eq!(
+        Packet::from(StimulusPortPage::new(1)),
+        Packet::StimulusPortPage(StimulusPortPage::new(1))
+    );
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn arbitrary() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let mut seed = 0x8765_4321u32;
+    let bytes = (0..64 * 1024)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) as u8
+        })
+        .collect::<Vec<_>>();
+
+    let mut u = Unstructured::new(&bytes);
+    while !u.is_empty() {
+        let packet = Packet::arbitrary(&mut u).unwrap();
+        let bytes = packet.to_bytes();
+
+        assert_eq!(bytes.len(), usize::from(packet.len()));
+        assert_eq!(
+            SliceDecoder::new(&bytes).next().unwrap().unwrap(),
+            packet,
+            "{:?}",
+            bytes
+        );
+    }
+}
+
+#[test]
+fn send() {
+    let mut decoder = Decoder::new();
+
+    // Instrumentation packet split across threads
+    decoder.feed(&[0x03, 0x10, 0x20]);
+    assert!(decoder.next().is_none());
+
+    let packet = std::thread::spawn(move || {
+        decoder.feed(&[0x30, 0x40]);
+        decoder.next()
+    })
+    .join()
+    .unwrap();
+
+    match packet.unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x10, 0x20, 0x30, 0x40]),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn one_shot() {
+    // trailing bytes are not consumed
+    match parse_one(&[0x03, 0x10, 0x20, 0x30, 0x40, 0x70]).unwrap() {
+        (Packet::Instrumentation(i), 5) => assert_eq!(i.payload(), &[0x10, 0x20, 0x30, 0x40]),
+        _ => panic!(),
+    }
+
+    match parse_one(&[0x04, 0x70]) {
+        Err(e @ Error::ReservedHeader { byte: 0x04 }) => assert_eq!(e.len(), 1),
+        _ => panic!(),
+    }
+
+    match parse_one(&[0x03, 0x10]) {
+        Err(Error::Truncated { header, len }) => {
+            assert_eq!(header, 0x03);
+            assert_eq!(len, 2);
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn timeline() {
+    let packets = [
+        Packet::from(Instrumentation::new(0, b"a")),
+        Packet::from(Instrumentation::new(0, b"b")),
+        Packet::from(LocalTimestamp::new(100, false, false)),
+        Packet::from(GTS1::new(1234, false, false)),
+        Packet::from(Instrumentation::new(0, b"c")),
+        Packet::from(LocalTimestamp::new(20, false, false)),
+        Packet::Overflow,
+    ];
+    let bytes = packets
+        .iter()
+        .flat_map(Packet::to_bytes)
+        .collect::<Vec<_>>();
+
+    let events = Timeline::new(SliceDecoder::new(&bytes))
+        .map(|res| {
+            let (timestamp, packet) = res.unwrap();
+            (timestamp.local(), packet)
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        events,
+        [
+            (100, packets[0]),
+            (100, packets[1]),
+            (120, packets[4]),
+            // not followed by a local timestamp
+            (120, packets[6]),
+        ]
+    );
+
+    // errors are yielded in order
+    let mut timeline = Timeline::new(SliceDecoder::new(&[
+        0x01, b'a', // Instrumentation
+        0x04, // Reserved header
+        0x01, b'b', // Instrumentation
+        0x30, // LTS2 (delta = 3)
+    ]));
+
+    match timeline.next().unwrap() {
+        Ok((timestamp, Packet::Instrumentation(i))) => {
+            assert_eq!(timestamp.local(), 3);
+            assert_eq!(i.payload(), b"a");
+        }
+        _ => panic!(),
+    }
+
+    match timeline.next().unwrap() {
+        Err(Error::ReservedHeader { byte }) => assert_eq!(byte, 0x04),
+        _ => panic!(),
+    }
+
+    match timeline.next().unwrap() {
+        Ok((timestamp, Packet::Instrumentation(i))) => {
+            assert_eq!(timestamp.local(), 3);
+            assert_eq!(i.payload(), b"b");
+        }
+        _ => panic!(),
+    }
+
+    assert!(timeline.next().is_none());
+}
+
+#[test]
+fn reset() {
+    let mut decoder = Decoder::builder().ignore(Kind::Overflow).build();
+
+    decoder.feed(&[0x70, 0x01, 0x10, 0x03, 0x20]);
+    assert!(decoder.next().unwrap().is_ok());
+    assert!(decoder.next().is_none());
+
+    // mid-packet
+    assert_eq!(decoder.pending_bytes(), &[0x03, 0x20]);
+
+    decoder.reset();
+    assert!(decoder.pending_bytes().is_empty());
+    assert_eq!(decoder.stats().bytes(), 0);
+
+    // the settings are kept
+    decoder.feed(&[0x70, 0x01, 0x30]);
+    match decoder.next().unwrap().unwrap() {
+        Packet::Instrumentation(i) => assert_eq!(i.payload(), &[0x30]),
+        _ => panic!(),
+    }
+    assert_eq!(decoder.offset(), 1);
+    assert!(decoder.pending_bytes().is_empty());
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_util() {
+    use proptest::{prop_assert_eq, proptest};
+
+    use crate::test_util;
+
+    proptest!(|((packets, bytes) in test_util::stream(test_util::packet(), 0..32))| {
+        let mut decoder = Decoder::new();
+        decoder.feed(&bytes);
+
+        prop_assert_eq!(decoder.collect::<Result<Vec<_>, _>>().unwrap(), packets);
+    });
+
+    proptest!(|(packet in test_util::weighted_packet(&[(0, Kind::Overflow), (1, Kind::GTS2)]))| {
+        prop_assert_eq!(packet.kind(), Kind::GTS2);
+    });
+}
+
+#[cfg(feature = "log")]
+#[test]
+fn log() {
+    use std::sync::Mutex;
+
+    use log::{Log, Metadata, Record};
+
+    static RECORDS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    struct Logger;
+
+    impl Log for Logger {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            RECORDS
+                .lock()
+                .unwrap()
+                .push((record.target().to_owned(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    log::set_logger(&Logger).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let mut stream = Stream::new(Cursor::new(&[0x70, 0x04, 0x01]), false);
+    while stream.next().unwrap().is_some() {}
+
+    let records = RECORDS.lock().unwrap();
+    for (target, message) in [
+        ("itm::io", "read 3 bytes"),
+        ("itm::decode", "offset=0 Overflow"),
+        ("itm::decode", "offset=1 reserved header byte: 4"),
+        ("itm::io", "EOF"),
+        (
+            "itm::decode",
+            "offset=2 truncated packet of length 1 with header 1",
+        ),
+    ] {
+        assert!(
+            records.iter().any(|r| r.0 == target && r.1 == message),
+            "{} {}",
+            target,
+            message
+        );
+    }
+}
+
+#[test]
+fn global_timestamp() {
+    let data = Packet::from(Instrumentation::new(0, b"a"));
+    let lts = Packet::from(LocalTimestamp::new(1, false, false));
+
+    let packets = [
+        // only the high-order bits are known
+        GTS2::new(0x12, false).into(),
+        data,
+        lts,
+        // full GTS1
+        GTS1::new(0x3ff_ffff, false, false).into(),
+        data,
+        lts,
+        // GTS1 that only carries the 7 low-order bits
+        GTS1::new(0x01, false, false).into(),
+        data,
+        lts,
+        // the high-order bits are stale until the next GTS2
+        GTS1::new(0x02, false, true).into(),
+        data,
+        lts,
+        GTS2::new(0x13, false).into(),
+        data,
+        lts,
+    ];
+    let bytes = packets
+        .iter()
+        .flat_map(Packet::to_bytes)
+        .collect::<Vec<_>>();
+
+    let globals = Timeline::new(SliceDecoder::new(&bytes))
+        .map(|res| res.unwrap().0.global())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        globals,
+        [
+            None,
+            Some(0x12 << 26 | 0x3ff_ffff),
+            Some(0x12 << 26 | 0x3ff_ff81),
+            None,
+            Some(0x13 << 26 | 0x0000_0002),
+        ]
+    );
+}
+
+#[test]
+fn instrumentation_page() {
+    let bytes = [
+        0x01, 0x10, // Instrumentation (port 0)
+        0x28, // Stimulus Port Page (page = 2)
+        0x09, 0x20, // Instrumentation (port 1)
+    ];
+
+    let mut decoder = Decoder::new();
+    decoder.feed(&bytes);
+
+    for packets in [
+        decoder.collect::<Vec<_>>(),
+        SliceDecoder::new(&bytes).collect(),
+    ] {
+        match packets[0] {
+            Ok(Packet::Instrumentation(i)) => assert_eq!((i.page(), i.address()), (0, 0)),
+            _ => panic!(),
+        }
+
+        match packets[2] {
+            Ok(Packet::Instrumentation(i)) => {
+                assert_eq!(i.page(), 2);
+                assert_eq!(i.port(), 1);
+                assert_eq!(i.address(), 65);
+                assert_eq!(i, Instrumentation::new(1, &[0x20]).with_page(2));
+            }
+            _ => panic!(),
+        }
+    }
+}
+
+#[test]
+fn hardware_source() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            // discriminator = 3 (reserved)
+            0x1f, 0x01, 0x02, 0x03, 0x04, //
+            // discriminator = 0 (Event counter) but 2-byte payload
+            0x06, 0x10, 0x20,
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::HardwareSource(hs) => {
+            assert_eq!(hs.discriminator(), 3);
+            assert_eq!(hs.payload(), &[0x01, 0x02, 0x03, 0x04]);
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::HardwareSource(hs) => {
+            assert_eq!(hs, HardwareSource::new(0, &[0x10, 0x20]));
+            assert_eq!(Packet::from(hs).to_bytes(), &[0x06, 0x10, 0x20]);
+        }
+        _ => panic!(),
+    }
+
+    // EOF
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[test]
+#[should_panic(expected = "not a generic hardware source packet")]
+fn hardware_source_known_type() {
+    // this is an event counter packet
+    HardwareSource::new(0, &[0x10]);
+}
+
+#[test]
+fn unknown_extension() {
+    let mut decoder = Decoder::new();
+
+    decoder.feed(&[
+        0x0c, // extension packet with SH = 1, no payload
+        0x98, 0x81, 0x02, // extension packet with a 2-byte payload
+        0x88, 0x81, 0x82, 0x83, 0x84, // extension packet with a 4-byte payload
+        0x01, b'a', // Instrumentation
+    ]);
+
+    match decoder.next().unwrap() {
+        Err(Error::UnknownExtension { header, len }) => assert_eq!((header, len), (0x0c, 1)),
+        _ => panic!(),
+    }
+
+    match decoder.next().unwrap() {
+        Err(Error::UnknownExtension { header, len }) => assert_eq!((header, len), (0x98, 3)),
+        _ => panic!(),
+    }
+
+    match decoder.next().unwrap() {
+        Err(Error::UnknownExtension { header, len }) => assert_eq!((header, len), (0x88, 5)),
+        _ => panic!(),
+    }
+
+    match decoder.next().unwrap() {
+        Ok(Packet::Instrumentation(i)) => assert_eq!(i.payload(), b"a"),
+        _ => panic!(),
+    }
+
+    assert!(decoder.next().is_none());
+    assert_eq!(decoder.stats().unknown_extensions(), 3);
+    assert_eq!(decoder.stats().reserved_headers(), 0);
+}
+
+#[test]
+fn data_trace_match() {
+    let mut stream = Stream::new(
+        Cursor::new(&[
+            0x65, 0x01, // Data trace match (comparator 2)
+            0x45, 0x02, // Data trace match with a reserved payload
+        ]),
+        false,
+    );
+
+    match stream.next().unwrap().unwrap().unwrap() {
+        Packet::DataTraceMatch(dtm) => {
+            assert_eq!(dtm.comparator(), 2);
+            assert_eq!(Packet::from(dtm).to_bytes(), &[0x65, 0x01]);
+        }
+        _ => panic!(),
+    }
+
+    match stream.next().unwrap().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => assert_eq!((header, len), (0x45, 1)),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn timeline_delayed() {
+    let packets = [
+        Packet::from(Instrumentation::new(0, b"a")),
+        Packet::from(LocalTimestamp::new(100, true, false)),
+        Packet::from(Instrumentation::new(0, b"b")),
+        Packet::from(LocalTimestamp::new(100, false, true)),
+        Packet::from(Instrumentation::new(0, b"c")),
+        Packet::from(LocalTimestamp::new(1, false, false)),
+        Packet::from(Instrumentation::new(0, b"d")),
+    ];
+    let bytes = packets
+        .iter()
+        .flat_map(Packet::to_bytes)
+        .collect::<Vec<_>>();
+
+    let flags = Timeline::new(SliceDecoder::new(&bytes))
+        .map(|res| {
+            let (timestamp, _) = res.unwrap();
+            (timestamp.timestamp_delayed(), timestamp.event_delayed())
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        flags,
+        [
+            (true, false),
+            (false, true),
+            (false, false),
+            // not followed by a local timestamp
+            (false, false),
+        ]
+    );
+}
+
+#[test]
+fn clock() {
+    let clock = Clock::new(8_000_000, 1);
+    assert_eq!(clock.duration(0), Duration::ZERO);
+    assert_eq!(clock.duration(1), Duration::from_nanos(125));
+    assert_eq!(clock.duration(12_000_001), Duration::new(1, 500_000_125));
+
+    // no overflow
+    assert_eq!(Clock::new(64, 64).duration(u64::MAX).as_secs(), u64::MAX);
+    assert_eq!(Clock::new(1, 64).duration(u64::MAX), Duration::MAX);
+
+    let timestamp = Timeline::new(SliceDecoder::new(&[0x01, b'a', 0x30]))
+        .next()
+        .unwrap()
+        .unwrap()
+        .0;
+    assert_eq!(
+        timestamp.local_duration(&Clock::new(1_000_000, 4)),
+        Duration::from_micros(12)
+    );
+}
+
+#[test]
+fn instrumentation_values() {
+    let i = Instrumentation::new(0, &[0x34, 0x12]);
+    assert_eq!(i.as_u16(), Some(0x1234));
+    assert_eq!(i.as_u32(), None);
+
+    let i = Instrumentation::new(0, &(-2i32).to_le_bytes());
+    assert_eq!(i.as_u16(), None);
+    assert_eq!(i.as_u32(), Some(0xffff_fffe));
+    assert_eq!(i.as_i32(), Some(-2));
+
+    let i = Instrumentation::new(0, &1.5f32.to_le_bytes());
+    assert_eq!(i.as_f32(), Some(1.5));
+
+    let i = Instrumentation::new(0, b"a");
+    assert_eq!(i.as_u16(), None);
+    assert_eq!(i.as_f32(), None);
+}
+
+#[test]
+fn strict() {
+    let bytes = [
+        0x1d, 0x01, // hardware source packet with a reserved discriminator (3)
+        0x01, b'a', // Instrumentation
+    ];
+
+    let mut decoder = Decoder::new();
+    decoder.feed(&bytes);
+    assert!(matches!(
+        decoder.next(),
+        Some(Ok(Packet::HardwareSource(_)))
+    ));
+
+    let mut decoder = Decoder::builder().strict().build();
+    decoder.feed(&bytes);
+
+    // the whole packet is skipped
+    match decoder.next().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => assert_eq!((header, len), (0x1d, 2)),
+        _ => panic!(),
+    }
+
+    assert_eq!(
+        decoder.next().unwrap().unwrap(),
+        Instrumentation::new(0, b"a").into()
+    );
+
+    assert_eq!(decoder.stats().malformed_packets(), 1);
+    assert_eq!(decoder.stats().packets(Kind::HardwareSource), 0);
+}
+
+#[test]
+fn source() {
+    assert_eq!(Packet::Overflow.source(), Source::Protocol);
+    assert_eq!(
+        Packet::from(LocalTimestamp::new(1, false, false)).source(),
+        Source::Protocol
+    );
+    assert_eq!(
+        Packet::from(Instrumentation::new(0, b"a")).source(),
+        Source::Instrumentation
+    );
+    assert_eq!(
+        Packet::from(ExceptionTrace::new(15, Function::Enter)).source(),
+        Source::Hardware
+    );
+    assert_eq!(Kind::HardwareSource.source(), Source::Hardware);
+}
+
+#[test]
+fn exception() {
+    for number in 0..1 << 9 {
+        assert_eq!(Exception::from_number(number).number(), number);
+    }
+    // out of the exception number range, but doesn't overflow
+    assert_eq!(Exception::Interrupt(u16::MAX).number(), 15);
+
+    let et = ExceptionTrace::new(3, Function::Enter);
+    assert_eq!(et.exception(), Exception::HardFault);
+    assert_eq!(et.exception().to_string(), "HardFault");
+
+    assert_eq!(Exception::from_number(13), Exception::Reserved(13));
+    assert_eq!(Exception::from_number(20).to_string(), "IRQ 4");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn timestamp_serde() {
+    let bytes = Packet::from(Instrumentation::new(0, b"a"))
+        .to_bytes()
+        .into_iter()
+        .chain(Packet::from(LocalTimestamp::new(100, true, false)).to_bytes())
+        .collect::<Vec<_>>();
+
+    let (timestamp, _) = Timeline::new(SliceDecoder::new(&bytes))
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(!timestamp.is_precise());
+
+    assert_eq!(
+        serde_json::to_string(&timestamp).unwrap(),
+        r#"{"event_delayed":false,"global":null,"local":100,"timestamp_delayed":true}"#
+    );
+}
+
+#[test]
+fn raw_bytes() {
+    let mut decoder = Decoder::new();
+    assert!(decoder.raw_bytes().is_empty());
+
+    // a packet split across `feed` calls
+    decoder.feed(&[0x04, 0x03, 0x01]);
+    assert!(decoder.next().unwrap().is_err());
+    assert_eq!(decoder.raw_bytes(), &[0x04]);
+    assert!(decoder.next().is_none());
+
+    decoder.feed(&[0x02, 0x03, 0x04, 0x02]);
+    match decoder.next().unwrap() {
+        Ok(Packet::Instrumentation(i)) => assert_eq!(i.payload(), &[1, 2, 3, 4]),
+        _ => panic!(),
+    }
+    assert_eq!(decoder.raw_bytes(), &[0x03, 0x01, 0x02, 0x03, 0x04]);
+
+    // retained across `feed` calls
+    assert!(decoder.next().is_none());
+    decoder.feed(&[0x10]);
+    assert_eq!(decoder.raw_bytes(), &[0x03, 0x01, 0x02, 0x03, 0x04]);
+    assert!(decoder.next().is_none());
+
+    assert!(decoder.finish().is_some());
+    assert_eq!(decoder.raw_bytes(), &[0x02, 0x10]);
+}
+
+#[test]
+fn wait_for_sync() {
+    let mut decoder = Decoder::builder().wait_for_sync().build();
+
+    // the tail of a packet followed by the start of a synchronization packet
+    decoder.feed(&[b'b', b'c', 0x01, b'd', 0x00, 0x00, 0x00]);
+    assert!(decoder.next().is_none());
+    assert_eq!(decoder.pending_bytes(), &[0x00, 0x00, 0x00]);
+
+    decoder.feed(&[0x00, 0x00, 0x80, 0x01, b'e']);
+    match decoder.next().unwrap() {
+        Ok(Packet::Synchronization(s)) => assert_eq!(s.len(), 6),
+        _ => panic!(),
+    }
+    assert_eq!(decoder.offset(), 4);
+
+    match decoder.next().unwrap() {
+        Ok(Packet::Instrumentation(i)) => assert_eq!(i.payload(), b"e"),
+        _ => panic!(),
+    }
+
+    // no more waiting once in sync
+    decoder.feed(&[0x01, b'f']);
+    assert!(decoder.next().unwrap().is_ok());
+
+    assert_eq!(decoder.stats().skipped_bytes(), 4);
+    assert_eq!(decoder.stats().bytes(), 14);
+    assert_eq!(decoder.stats().total_packets(), 3);
+}
+
+#[test]
+fn unknown_headers() {
+    let bytes = [
+        0x01, b'a', // Instrumentation
+        0x04, // Reserved header
+        0x0c, // Unknown extension packet
+        0x01, b'b', // Instrumentation
+    ];
+
+    let decode = |policy| {
+        let mut decoder = Decoder::builder().unknown_headers(policy).build();
+        decoder.feed(&bytes);
+        let items = decoder.by_ref().map(|res| res.is_ok()).collect::<Vec<_>>();
+        assert_eq!(decoder.stats().reserved_headers(), 1);
+        (items, decoder.pending_bytes().len())
+    };
+
+    assert_eq!(
+        decode(HeaderPolicy::Report),
+        (vec![true, false, false, true], 0)
+    );
+    assert_eq!(decode(HeaderPolicy::Skip), (vec![true, true], 0));
+    assert_eq!(decode(HeaderPolicy::Abort), (vec![true, false], 3));
+
+    let mut decoder = Decoder::builder()
+        .unknown_headers(HeaderPolicy::Abort)
+        .build();
+    decoder.feed(&bytes);
+    while decoder.next().is_some() {}
+    assert!(decoder.finish().is_none());
+
+    // until reset
+    decoder.reset();
+    decoder.feed(&bytes);
+    assert!(decoder.next().unwrap().is_ok());
+}
+
+#[test]
+fn resync() {
+    let mut decoder = Decoder::builder().resync_after(2).build();
+
+    decoder.feed(&[
+        0x01, b'a', // Instrumentation
+        0x04, // Reserved header
+        0x01, b'b', // Instrumentation
+        0x04, // Reserved header
+        0x01, b'c', 0x01, b'd', // discarded
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Synchronization
+        0x01, b'e', // Instrumentation
+    ]);
+
+    let items = decoder.by_ref().map(|res| res.is_ok()).collect::<Vec<_>>();
+    assert_eq!(items, [true, false, true, false, true, true]);
+    assert_eq!(decoder.raw_bytes(), &[0x01, b'e']);
+
+    let stats = decoder.stats();
+    assert_eq!(stats.resyncs(), 1);
+    assert_eq!(stats.skipped_bytes(), 4);
+    assert_eq!(stats.bytes(), 18);
+    assert_eq!(stats.packets(Kind::Synchronization), 1);
+}
+
+#[test]
+fn tpiu() {
+    let frames = [
+        // not aligned yet; discarded
+        0x03,
+        0x01, //
+        // full synchronization packet
+        0xff,
+        0xff,
+        0xff,
+        0x7f, //
+        // source 1: 0x01 0x02 (even data byte with LSB set), then an ID change to source 2 that
+        // takes effect after the next byte
+        0x03,
+        0x01,
+        0x02,
+        0x03,
+        0x05,
+        0x04,
+        0x0a,
+        0x0b,
+        0x0c,
+        0x0d,
+        0x0e,
+        0x0f,
+        0x10,
+        0x11,
+        0x12,
+        0b0000_0110,
+        // source 2 continues; the last even byte switches to the null source
+        0x20,
+        0x21,
+        0x22,
+        0x23,
+        0x24,
+        0x25,
+        0x26,
+        0x27,
+        0x28,
+        0x29,
+        0x2a,
+        0x2b,
+        0x2c,
+        0x2d,
+        0x01,
+        0x00,
+        // a partial frame, dropped by the synchronization packet that follows it
+        0x03,
+        0x55,
+        0x56, //
+        0xff,
+        0xff,
+        0xff,
+        0x7f, //
+        0x03,
+        0x66,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+        0x01,
+        0x00,
+    ];
+
+    let mut out = vec![];
+    let mut deframer = Deframer::new();
+    // byte by byte
+    for byte in frames.chunks(1) {
+        deframer.feed(byte, |id, bytes| out.push((id, bytes.to_vec())));
+    }
+
+    assert_eq!(
+        out,
+        [
+            (1, vec![0x01, 0x03, 0x03, 0x04]),
+            (
+                2,
+                vec![0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12]
+            ),
+            (
+                2,
+                vec![
+                    0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27, 0x28, 0x29, 0x2a, 0x2b, 0x2c,
+                    0x2d
+                ]
+            ),
+            (
+                1,
+                vec![0x66, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+            ),
+        ]
+    );
+    assert_eq!(deframer.id(), tpiu::NULL_ID);
+}
+
+#[test]
+fn tpiu_detect() {
+    let mut framed = vec![0x01, 0x02];
+    framed.extend_from_slice(&tpiu::FULL_SYNC);
+    framed.extend_from_slice(&[
+        0x03, 0x01, 0x02, 0x03, 0x05, 0x04, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12,
+        0x06,
+    ]);
+    assert_eq!(tpiu::detect(&framed), Some(Format::Tpiu));
+    // no complete frame after the synchronization packet
+    assert_eq!(tpiu::detect(&framed[..framed.len() - 1]), None);
+
+    // an instrumentation packet with payload 0x7fffffff, followed by a byte that can't start a
+    // frame
+    let mut raw = vec![0x03, 0xff, 0xff, 0xff, 0x7f, 0xff];
+    raw.extend_from_slice(&[0x01; 15]);
+    assert_eq!(tpiu::detect(&raw), None);
+    raw.extend_from_slice(&[0, 0, 0, 0, 0, 0x80]);
+    assert_eq!(tpiu::detect(&raw), Some(Format::Raw));
+}
+
+// NRZ encodes `bytes` at `num / den` samples per bit
+fn nrz_samples(bytes: &[u8], num: u64, den: u64) -> Vec<bool> {
+    let mut bits = vec![true; 4];
+    for &byte in bytes {
+        bits.push(false);
+        bits.extend((0..8).map(|i| byte & (1 << i) != 0));
+        bits.push(true);
+    }
+    bits.extend([true; 4]);
+
+    // sample `i` sees the bit at time `i * den / num`
+    (0..bits.len() as u64 * num / den)
+        .map(|i| bits[(i * den / num) as usize])
+        .collect()
+}
+
+#[test]
+fn nrz() {
+    let input = [0x01, b'a', 0x00, 0xff, 0x55];
+
+    for &(sample_rate, baud_rate) in &[(8_000_000, 2_000_000), (7_000_000, 2_000_000)] {
+        let samples = nrz_samples(&input, sample_rate, baud_rate);
+
+        let mut nrz = Nrz::new(sample_rate as u32, baud_rate as u32);
+        let mut bytes = vec![];
+        // in chunks
+        for chunk in samples.chunks(5) {
+            nrz.feed(chunk.iter().cloned(), |byte| bytes.push(byte));
+        }
+
+        assert_eq!(bytes, input);
+        assert_eq!(nrz.framing_errors(), 0);
+    }
+
+    // wrong baud rate
+    let samples = nrz_samples(&[0x00, 0x00], 8, 1);
+    let mut nrz = Nrz::new(8_000_000, 2_000_000);
+    nrz.feed(samples, |_| panic!());
+    assert_eq!(nrz.framing_errors(), 2);
+}
+
+// `bytes` as one Manchester packet sampled `num / den` times per bit
+fn manchester_samples(bytes: &[u8], num: u64, den: u64) -> Vec<bool> {
+    let mut bits = vec![];
+    // start bit
+    bits.push(true);
+    for &byte in bytes {
+        bits.extend((0..8).map(|i| byte & (1 << i) != 0));
+    }
+
+    // half bits, surrounded by idle time
+    let mut halves = vec![false; 4];
+    for bit in bits {
+        halves.extend([bit, !bit]);
+    }
+    halves.extend([false; 4]);
+
+    // sample `i` sees the half bit at time `2 * i * den / num`
+    (0..halves.len() as u64 * num / den / 2)
+        .map(|i| halves[(2 * i * den / num) as usize])
+        .collect()
+}
+
+#[test]
+fn manchester() {
+    let input = [0x01, b'a', 0x00, 0xff, 0x55];
+
+    // 7 and 9 samples per bit decoded as if there were 8
+    for &sample_rate in &[8_000_000, 7_000_000, 9_000_000] {
+        let samples = manchester_samples(&input, sample_rate, 1_000_000);
+
+        let mut manchester = Manchester::new(8_000_000, 1_000_000);
+        let mut bytes = vec![];
+        // in chunks, and twice
+        for chunk in samples.chunks(5).chain(samples.chunks(5)) {
+            manchester.feed(chunk.iter().cloned(), |byte| bytes.push(byte));
+        }
+
+        assert_eq!(bytes, [input, input].concat(), "{}", sample_rate);
+        assert_eq!(manchester.framing_errors(), 0);
+    }
+
+    // a packet that ends in the middle of a byte: idle, start bit and 4 data bits, then idle
+    let mut samples = manchester_samples(&[0xaa], 8, 1);
+    samples.truncate(16 + 8 * 5);
+    samples.extend([false; 16]);
+    let mut manchester = Manchester::new(8, 1);
+    manchester.feed(samples, |_| panic!());
+    assert_eq!(manchester.framing_errors(), 1);
+}
+
+#[test]
+fn detect_baud_rate() {
+    // an ITM stream with a sync packet
+    let input = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, b'a', 0x03, b'b', b'c',
+    ];
+
+    for &(sample_rate, baud_rate) in &[
+        (8_000_000, 1_000_000),
+        (17_000_000, 2_000_000),
+        (100_000_000, 12_000_000),
+    ] {
+        let samples = nrz_samples(&input, sample_rate, baud_rate);
+        let detected = swo::detect_baud_rate(sample_rate as u32, &samples).unwrap();
+
+        // within 1%
+        assert!(
+            (i64::from(detected) - baud_rate as i64).abs() * 100 < baud_rate as i64,
+            "{} != {}",
+            detected,
+            baud_rate
+        );
+
+        let mut nrz = Nrz::new(sample_rate as u32, detected);
+        let mut bytes = vec![];
+        nrz.feed(samples, |byte| bytes.push(byte));
+        assert_eq!(bytes, input);
+    }
+
+    assert_eq!(swo::detect_baud_rate(1_000_000, &[true; 64]), None);
+}
+
+#[test]
+fn tpiu_framer() {
+    let input: Vec<u8> = (0..=255).collect();
+
+    // switch trace sources at every position of a frame, in both directions
+    for run in 1..=16 {
+        let mut framed = vec![];
+        let mut framer = Framer::new();
+        for (i, chunk) in input.chunks(run).enumerate() {
+            let id = if i % 3 == 2 { 2 } else { 1 };
+            framer.feed(id, chunk, |bytes| framed.extend_from_slice(bytes));
+        }
+        framer.flush(|bytes| framed.extend_from_slice(bytes));
+
+        assert_eq!(framed[..4], tpiu::FULL_SYNC);
+        assert_eq!((framed.len() - 4) % tpiu::FRAME_SIZE, 0);
+
+        let mut expected = [vec![], vec![]];
+        for (i, chunk) in input.chunks(run).enumerate() {
+            expected[usize::from(i % 3 == 2)].extend_from_slice(chunk);
+        }
+
+        let mut deframed = [vec![], vec![]];
+        let mut deframer = Deframer::new();
+        deframer.feed(&framed, |id, bytes| {
+            deframed[usize::from(id - 1)].extend_from_slice(bytes)
+        });
+
+        assert_eq!(deframed, expected, "{}", run);
+    }
+
+    // nothing to flush
+    let mut framer = Framer::new();
+    framer.flush(|_| panic!());
+}
+
+#[test]
+fn stream_reconnect() {
+    // an instrumentation packet and the start of another one
+    let mut stream = Stream::new(Cursor::new(vec![0x01, b'a', 0x03, b'b']), false);
+    assert_eq!(
+        stream.next().unwrap().unwrap().unwrap(),
+        Instrumentation::new(0, b"a").into()
+    );
+
+    // the source is reopened
+    *stream.get_mut() = Cursor::new(vec![0x01, b'c']);
+    stream.decoder_mut().reset();
+
+    assert_eq!(
+        stream.next().unwrap().unwrap().unwrap(),
+        Instrumentation::new(0, b"c").into()
+    );
+    assert!(stream.next().unwrap().is_none());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_invariants() {
+    // packets that can't be encoded
+    for json in &[
+        r#"{"Synchronization":{"len":0}}"#,
+        r#"{"Instrumentation":{"page":0,"payload":[1],"port":32}}"#,
+        r#"{"Instrumentation":{"page":8,"payload":[1],"port":0}}"#,
+        // LTS2 can't encode a delta of 7
+        r#"{"LocalTimestamp":{"delta":7,"tc":0,"len":1}}"#,
+        r#"{"LocalTimestamp":{"delta":128,"tc":0,"len":2}}"#,
+        r#"{"LocalTimestamp":{"delta":1,"tc":4,"len":2}}"#,
+        r#"{"GTS1":{"bits":1,"clk_ch":true,"len":2,"wrap":false}}"#,
+        r#"{"GTS2":{"bits":4194304,"b64":false}}"#,
+        r#"{"StimulusPortPage":{"page":8}}"#,
+        r#"{"EventCounter":{"payload":64}}"#,
+        r#"{"ExceptionTrace":{"function":"Enter","number":512}}"#,
+        r#"{"DataTracePcValue":{"cmpn":4,"pc":0}}"#,
+        r#"{"DataTraceMatch":{"cmpn":4}}"#,
+        // an event counter packet
+        r#"{"HardwareSource":{"disc":0,"payload":[0]}}"#,
+    ] {
+        assert!(serde_json::from_str::<Packet>(json).is_err(), "{}", json);
+    }
+
+    // decoded packets, including the ones that don't use the shortest encoding, round trip
+    let bytes = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, // Synchronization
+        0xc0, 0x81, 0x00, // LTS1 with a redundant payload byte
+        0x94, 0x81, 0x80, 0x80, 0x60, // GTS1 with both flags set
+        0x17, 0x01, 0x02, 0x03, 0x04, // HardwareSource
+    ];
+    for packet in SliceDecoder::new(&bytes) {
+        let packet = packet.unwrap();
+        let json = serde_json::to_string(&packet).unwrap();
+        assert_eq!(serde_json::from_str::<Packet>(&json).unwrap(), packet);
+    }
+}
+
+#[test]
+fn timeline_decoder() {
+    let mut timeline = Timeline::from_decoder(Decoder::new());
+
+    // the local timestamp packet of "a" arrives in the next chunk
+    timeline.feed(&[0x01, b'a']);
+    assert!(timeline.next().is_none());
+
+    // LTS1 with the TimestampDelayed flag, then "b" and the first byte of "c"
+    timeline.feed(&[0xd0, 0x05, 0x01, b'b', 0x01]);
+    let (timestamp, packet) = timeline.next().unwrap().unwrap();
+    assert_eq!(packet, Instrumentation::new(0, b"a").into());
+    assert_eq!(timestamp.local(), 5);
+    assert!(timestamp.timestamp_delayed());
+    assert!(timeline.next().is_none());
+
+    // no more timestamps; "b" and the truncated packet are yielded at the end of the input
+    timeline.finish();
+    let (timestamp, packet) = timeline.next().unwrap().unwrap();
+    assert_eq!(packet, Instrumentation::new(0, b"b").into());
+    assert_eq!(timestamp.local(), 5);
+    assert!(!timestamp.timestamp_delayed());
+    match timeline.next().unwrap().unwrap_err() {
+        Error::Truncated { header, len } => assert_eq!((header, len), (0x01, 1)),
+        e => panic!("{:?}", e),
+    }
+    assert!(timeline.next().is_none());
+}
+
+#[test]
+fn architecture() {
+    let bytes = [
+        0x65, 0x01, // Data trace match (comparator 2)
+        0x01, b'a', // Instrumentation
+    ];
+
+    let mut decoder = Decoder::builder()
+        .architecture(Architecture::ARMv7M)
+        .build();
+    decoder.feed(&bytes);
+
+    // the whole packet is skipped
+    match decoder.next().unwrap() {
+        Err(Error::MalformedPacket { header, len }) => assert_eq!((header, len), (0x65, 2)),
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(
+        decoder.next().unwrap().unwrap(),
+        Instrumentation::new(0, b"a").into()
+    );
+    assert_eq!(decoder.stats().malformed_packets(), 1);
+
+    let mut decoder = Decoder::builder()
+        .architecture(Architecture::ARMv8M)
+        .build();
+    decoder.feed(&bytes);
+    assert_eq!(
+        decoder.next().unwrap().unwrap().kind(),
+        Kind::DataTraceMatch
+    );
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn exception_serde() {
+    for number in 0..1 << 9 {
+        let exception = Exception::from_number(number);
+        let json = serde_json::to_string(&exception).unwrap();
+        assert_eq!(serde_json::from_str::<Exception>(&json).unwrap(), exception);
+    }
+
+    assert_eq!(
+        serde_json::to_string(&Exception::Interrupt(1)).unwrap(),
+        r#"{"Interrupt":1}"#
+    );
+
+    // values that no exception number maps to
+    for json in &[
+        r#"{"Interrupt":496}"#,
+        r#"{"Reserved":3}"#,
+        r#"{"Reserved":16}"#,
+    ] {
+        assert!(serde_json::from_str::<Exception>(json).is_err(), "{}", json);
+    }
+}
+
+#[test]
+fn wait_for_sync_zeros() {
+    let mut decoder = Decoder::builder().wait_for_sync().build();
+
+    // a long run of zeros doesn't pile up in the decoder
+    for _ in 0..10 {
+        decoder.feed(&[0; 100]);
+        assert!(decoder.next().is_none());
+    }
+    assert_eq!(decoder.pending_bytes().len(), 254);
+
+    // the longest synchronization packet the decoder can represent
+    decoder.feed(&[0x80]);
+    match decoder.next().unwrap() {
+        Ok(Packet::Synchronization(s)) => assert_eq!(s.len(), 255),
+        res => panic!("{:?}", res),
+    }
+    assert_eq!(decoder.offset(), 746);
+    assert_eq!(decoder.stats().skipped_bytes(), 746);
+
+    // the zeros at the end of the input are one truncated packet
+    let mut decoder = Decoder::builder().wait_for_sync().build();
+    decoder.feed(&[0; 1000]);
+    assert!(decoder.next().is_none());
+    match decoder.finish().unwrap() {
+        Error::Truncated { header, len } => assert_eq!((header, len), (0x00, 254)),
+        e => panic!("{:?}", e),
+    }
+    assert_eq!(decoder.stats().bytes(), 1000);
+}
+
+#[test]
+fn framing() {
+    // "hi" written to stimulus port 0 by the ITM, interleaved with the data of another source
+    let mut framed = vec![];
+    let mut framer = Framer::new();
+    framer.feed(1, &[0x01, b'h'], |bytes| framed.extend_from_slice(bytes));
+    framer.feed(2, &[0x01, 0x02, 0x03], |bytes| {
+        framed.extend_from_slice(bytes)
+    });
+    framer.feed(1, &[0x01, b'i'], |bytes| framed.extend_from_slice(bytes));
+    framer.flush(|bytes| framed.extend_from_slice(bytes));
+
+    let mut decoder = Decoder::builder().framing(Format::Tpiu).build();
+    for byte in &framed {
+        decoder.feed(&[*byte]);
+    }
+
+    let text = decoder
+        .by_ref()
+        .map(|res| match res.unwrap() {
+            Packet::Instrumentation(i) => i.payload().to_vec(),
+            packet => panic!("{:?}", packet),
+        })
+        .collect::<Vec<_>>()
+        .concat();
+    assert_eq!(text, b"hi");
+    assert!(decoder.finish().is_none());
+
+    // offsets count the ITM bytes
+    assert_eq!(decoder.offset(), 2);
+    assert_eq!(decoder.stats().bytes(), 4);
+
+    // raw input is not deframed
+    let mut decoder = Decoder::builder().framing(Format::Raw).build();
+    decoder.feed(&[0x01, b'h']);
+    match decoder.next() {
+        Some(Ok(Packet::Instrumentation(i))) => assert_eq!(i.payload(), b"h"),
+        res => panic!("{:?}", res),
+    }
+}
+
+#[test]
+fn tpiu_sync_straddle() {
+    // source 1 then data bytes; `fill` is the first data byte
+    fn frame(fill: u8) -> Vec<u8> {
+        let mut frame = vec![0x03];
+        frame.extend((0..14).map(|i| fill + 2 * i));
+        frame.push(0x00);
+        frame
+    }
+
+    let mut input = tpiu::FULL_SYNC.to_vec();
+    input.extend(frame(0x10));
+    // the first 14 bytes of a frame, interrupted by a synchronization packet whose first two
+    // bytes would complete it
+    input.extend(&frame(0x40)[..14]);
+    input.extend_from_slice(&tpiu::FULL_SYNC);
+    // half-word synchronization packets between frames and at an even position of a frame
+    input.extend_from_slice(&tpiu::HALF_SYNC);
+    let mut last = frame(0x70);
+    last.splice(4..4, tpiu::HALF_SYNC.iter().cloned());
+    input.extend(last);
+
+    let mut out = vec![];
+    let mut deframer = Deframer::new();
+    for byte in input.chunks(1) {
+        deframer.feed(byte, |id, bytes| {
+            assert_eq!(id, 1);
+            out.extend_from_slice(bytes);
+        });
+    }
+
+    let expected = [frame(0x10), frame(0x70)]
+        .iter()
+        .flat_map(|frame| frame[1..15].to_vec())
+        .collect::<Vec<_>>();
+    assert_eq!(out, expected);
+
+    // 0xff data bytes at odd positions, the last one followed by an ID change to source 0x3f; the
+    // flags byte, 0xff, delays the first ID change and sets the LSB of the even data bytes
+    let mut input = tpiu::FULL_SYNC.to_vec();
+    input.extend_from_slice(&[
+        0x03, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x00, 0xff, 0x7f,
+        0xff,
+    ]);
+    let mut out = vec![];
+    let mut deframer = Deframer::new();
+    deframer.feed(&input, |_, bytes| out.extend_from_slice(bytes));
+    // the last 0xff could start a synchronization packet
+    assert!(out.is_empty());
+    deframer.feed(&[0x01], |_, bytes| out.extend_from_slice(bytes));
+    assert_eq!(
+        out,
+        [0x01, 0xff, 0x01, 0xff, 0x01, 0xff, 0x01, 0xff, 0x01, 0xff, 0x01, 0xff]
+    );
+    assert_eq!(deframer.id(), 0x3f);
+}
+
+#[test]
+fn tpiu_detect_mid_frame() {
+    // the capture starts in the middle of a frame whose ITM data includes a synchronization
+    // packet: source 1, then the five zeros and 0x80 of the packet
+    let mut framed = vec![
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+    framed.extend_from_slice(&tpiu::FULL_SYNC);
+    framed.extend_from_slice(&[
+        0x03, 0x01, 0x02, 0x03, 0x05, 0x04, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12,
+        0x06,
+    ]);
+    assert_eq!(tpiu::detect(&framed[2..]), Some(Format::Tpiu));
+
+    // the bytes after an ITM synchronization packet must decode as ITM packets; 0x04 is a reserved
+    // header
+    let mut bogus = vec![0, 0, 0, 0, 0, 0x80, 0x04];
+    assert_eq!(tpiu::detect(&bogus), None);
+    bogus.extend_from_slice(&[0, 0, 0, 0, 0, 0x80]);
+    assert_eq!(tpiu::detect(&bogus), Some(Format::Raw));
+}
+
+#[test]
+fn comparator() {
+    let packets = [
+        Packet::DataTracePcValue(DataTracePcValue::new(0, 0x0800_0000)),
+        Packet::DataTraceAddress(DataTraceAddress::new(1, 0x0400)),
+        Packet::DataTraceDataValue(DataTraceDataValue::new(2, true, &[0x00, 0x04, 0x00, 0x20])),
+        Packet::DataTraceMatch(DataTraceMatch::new(3)),
+    ];
+    for (i, packet) in packets.iter().enumerate() {
+        assert_eq!(packet.comparator(), Some(i as u8), "{:?}", packet);
+    }
+
+    assert_eq!(
+        Packet::Instrumentation(Instrumentation::new(0, b"a")).comparator(),
+        None
+    );
+}
+
+#[test]
+fn exception_display_with() {
+    let irq_names = |irq| match irq {
+        0 => Some("WWDG"),
+        38 => Some("USART2"),
+        _ => None,
+    };
+
+    let name = |number| {
+        Exception::from_number(number)
+            .display_with(irq_names)
+            .to_string()
+    };
+    assert_eq!(name(16), "WWDG");
+    assert_eq!(name(54), "USART2");
+    // no device-specific name
+    assert_eq!(name(17), "IRQ 1");
+    // not an external interrupt
+    assert_eq!(name(3), "HardFault");
+    assert_eq!(name(8), "reserved exception 8");
+}
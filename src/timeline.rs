@@ -0,0 +1,320 @@
+//! Timestamped ITM events
+
+use std::{collections::VecDeque, convert::TryFrom, time::Duration};
+
+use crate::{
+    packet::{LocalTimestamp, GTS1, GTS2},
+    Decoder, Error, Packet,
+};
+
+/// Merges the timestamp packets of an ITM stream with the packets they refer to
+///
+/// A local timestamp packet is emitted *after* the data packets it timestamps, so `Timeline`
+/// holds data packets until the next local timestamp packet arrives and then yields them paired
+/// with that timestamp. Timestamp packets are consumed by `Timeline` and not yielded. Decoding
+/// errors are yielded in stream order.
+///
+/// Packets that are not followed by a local timestamp packet before the end of the input, which
+/// is the case for all packets if the target has local timestamps disabled, are yielded at the end
+/// of the input with the latest timestamp. For a timeline created with `new` the end of the input
+/// is the first `None` from `packets`; for one created with `from_decoder` it's the call to
+/// `finish`.
+///
+/// The local timestamp packet also reports how precise the association is; see
+/// `Timestamp::timestamp_delayed` and `Timestamp::event_delayed`.
+///
+/// The full global timestamp is reconstructed from the GTS1 (low-order bits) and GTS2 (high-order
+/// bits) packets; see `Timestamp::global`.
+#[derive(Debug)]
+pub struct Timeline<I> {
+    // has the end of the input been reached once `packets` returns `None`?
+    end_of_input: bool,
+    global: GlobalTimestamp,
+    // local time accumulated from the local timestamp packets seen so far
+    local: u64,
+    packets: I,
+    // packets (and errors) waiting for their timestamp
+    pending: VecDeque<Result<Packet, Error>>,
+    // number of items at the front of `pending` that have been timestamped
+    ready: usize,
+    // timestamp of the `ready` items
+    timestamp: Timestamp,
+}
+
+impl<I> Timeline<I>
+where
+    I: Iterator<Item = Result<Packet, Error>>,
+{
+    /// Creates a timeline from a sequence of decoded packets that ends with the input, e.g. a
+    /// `Stream` or a `SliceDecoder`
+    ///
+    /// The first `None` returned by `packets` is taken as the end of the input. Use `from_decoder`
+    /// to timestamp the packets of a push `Decoder`, where `None` only means that more bytes are
+    /// needed.
+    pub fn new(packets: I) -> Timeline<I> {
+        Timeline {
+            end_of_input: true,
+            global: GlobalTimestamp::default(),
+            local: 0,
+            packets,
+            pending: VecDeque::new(),
+            ready: 0,
+            timestamp: Timestamp::default(),
+        }
+    }
+
+    /// Gets a reference to the underlying packet iterator.
+    pub fn get_ref(&self) -> &I {
+        &self.packets
+    }
+
+    /// Gets a mutable reference to the underlying packet iterator.
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.packets
+    }
+
+    // timestamps all the pending items with the current local time; `lts` is the local timestamp
+    // packet that timestamps them, if any
+    fn release(&mut self, lts: Option<&LocalTimestamp>) {
+        self.ready = self.pending.len();
+        self.timestamp = Timestamp {
+            event_delayed: lts.is_some_and(LocalTimestamp::event_delayed),
+            global: self.global.value(),
+            local: self.local,
+            timestamp_delayed: lts.is_some_and(LocalTimestamp::timestamp_delayed),
+        };
+    }
+}
+
+impl<I> Iterator for Timeline<I>
+where
+    I: Iterator<Item = Result<Packet, Error>>,
+{
+    type Item = Result<(Timestamp, Packet), Error>;
+
+    fn next(&mut self) -> Option<Result<(Timestamp, Packet), Error>> {
+        loop {
+            if self.ready != 0 {
+                self.ready -= 1;
+                let timestamp = self.timestamp;
+                return self
+                    .pending
+                    .pop_front()
+                    .map(|res| res.map(|packet| (timestamp, packet)));
+            }
+
+            match self.packets.next() {
+                Some(Ok(Packet::LocalTimestamp(lts))) => {
+                    self.local += u64::from(lts.delta());
+                    self.release(Some(&lts));
+                }
+                Some(Ok(Packet::GTS1(gts))) => self.global.update_low(&gts),
+                Some(Ok(Packet::GTS2(gts))) => self.global.update_high(&gts),
+                Some(res) => self.pending.push_back(res),
+                None => {
+                    if self.pending.is_empty() || !self.end_of_input {
+                        return None;
+                    }
+
+                    self.release(None);
+                }
+            }
+        }
+    }
+}
+
+impl Timeline<Decoder> {
+    /// Creates a timeline from the packets of a push `Decoder`
+    ///
+    /// Bytes are pushed into the timeline using the `feed` method. Packets are held until their
+    /// local timestamp packet arrives, which may be in a later chunk of bytes; call `finish` at the
+    /// end of the input to also yield the packets that have none.
+    pub fn from_decoder(decoder: Decoder) -> Timeline<Decoder> {
+        Timeline {
+            end_of_input: false,
+            ..Timeline::new(decoder)
+        }
+    }
+
+    /// Pushes bytes into the decoder
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.end_of_input = false;
+        self.packets.feed(bytes);
+    }
+
+    /// Signals the end of the input
+    ///
+    /// The packets that are still waiting for a local timestamp packet, and the truncated packet
+    /// the decoder may hold (see `Decoder::finish`), are then yielded with the latest timestamp.
+    /// Call this after the timeline has returned `None` for the last chunk of bytes.
+    pub fn finish(&mut self) {
+        self.end_of_input = true;
+
+        if let Some(e) = self.packets.finish() {
+            self.pending.push_back(Err(e));
+        }
+    }
+}
+
+// The global timestamp, pieced together from GTS1 and GTS2 packets
+#[derive(Clone, Copy, Debug, Default)]
+struct GlobalTimestamp {
+    // TS[63:26] or TS[47:26]; `None` if unknown or stale
+    high: Option<u64>,
+    // TS[25:0]; `None` until the ITM sends all the bits
+    low: Option<u32>,
+}
+
+impl GlobalTimestamp {
+    fn update_low(&mut self, gts: &GTS1) {
+        // the ITM omits the high-order payload bytes whose bits haven't changed since the previous
+        // GTS1 packet
+        let width = if gts.len == 5 {
+            26
+        } else {
+            7 * u32::from(gts.len - 1)
+        };
+
+        if width == 26 {
+            self.low = Some(gts.bits);
+        } else if let Some(low) = &mut self.low {
+            let mask = (1 << width) - 1;
+            *low = (*low & !mask) | gts.bits;
+        }
+
+        if gts.has_wrapped() {
+            // the high-order bits have changed; the new ones come in the next GTS2 packet
+            self.high = None;
+        }
+    }
+
+    fn update_high(&mut self, gts: &GTS2) {
+        self.high = Some(gts.bits);
+    }
+
+    fn value(&self) -> Option<u64> {
+        Some(self.high? << 26 | u64::from(self.low?))
+    }
+}
+
+/// The time at which an ITM event happened
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Timestamp {
+    event_delayed: bool,
+    global: Option<u64>,
+    local: u64,
+    timestamp_delayed: bool,
+}
+
+impl Timestamp {
+    /// The global timestamp as of the local timestamp packet that timestamped the event
+    ///
+    /// This is the latest 48-bit or 64-bit value reconstructed from the global timestamp packets.
+    /// It's `None` until the ITM has sent both halves of the value, and while the high-order half
+    /// is stale (after a GTS1 packet reports that it has wrapped, until the next GTS2 packet).
+    pub fn global(&self) -> Option<u64> {
+        self.global
+    }
+
+    /// Local time, in timestamp clock cycles, elapsed since the start of the trace
+    ///
+    /// This is the sum of the deltas of all the local timestamp packets received up to, and
+    /// including, the one that timestamped the event
+    pub fn local(&self) -> u64 {
+        self.local
+    }
+
+    /// The local time is exact: neither `timestamp_delayed` nor `event_delayed` is set
+    ///
+    /// When this is `false` the time printed for the event is approximate
+    pub fn is_precise(&self) -> bool {
+        !self.timestamp_delayed && !self.event_delayed
+    }
+
+    /// The timestamp was sampled after the event's packet was generated
+    ///
+    /// `local` is then an upper bound of the time at which the packet was generated, rather than
+    /// the exact time. This is always `false` for events that were not followed by a local
+    /// timestamp packet.
+    pub fn timestamp_delayed(&self) -> bool {
+        self.timestamp_delayed
+    }
+
+    /// The output of the event's packet was delayed relative to the event itself
+    ///
+    /// `local` is then the time at which the packet was generated, which is later than the time of
+    /// the event. This is always `false` for events that were not followed by a local timestamp
+    /// packet.
+    pub fn event_delayed(&self) -> bool {
+        self.event_delayed
+    }
+
+    /// Local time elapsed since the start of the trace, converted to wall-clock time using the
+    /// given timestamp `clock`
+    pub fn local_duration(&self, clock: &Clock) -> Duration {
+        clock.duration(self.local)
+    }
+}
+
+/// The clock that drives the local timestamp counter
+///
+/// Converts timestamp counts into wall-clock time
+///
+/// ```
+/// use std::time::Duration;
+///
+/// use itm::timeline::Clock;
+///
+/// // 72 MHz trace clock divided by 16 (TSPrescale = 0b10)
+/// let clock = Clock::new(72_000_000, 16);
+/// assert_eq!(clock.duration(4_500), Duration::from_millis(1));
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Clock {
+    frequency: u32,
+    prescaler: u8,
+}
+
+impl Clock {
+    /// Creates a clock from the frequency, in Hz, of the timestamp counter's source clock and
+    /// the ITM prescaler that divides it (the `TSPrescale` field of the `ITM_TCR` register)
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `frequency` is zero or if `prescaler` is not 1, 4, 16 or 64
+    pub fn new(frequency: u32, prescaler: u8) -> Clock {
+        assert!(frequency != 0, "frequency must not be zero");
+        assert!(
+            [1, 4, 16, 64].contains(&prescaler),
+            "prescaler must be 1, 4, 16 or 64"
+        );
+
+        Clock {
+            frequency,
+            prescaler,
+        }
+    }
+
+    /// Frequency of the source clock in Hz
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    /// Prescaler applied to the source clock
+    pub fn prescaler(&self) -> u8 {
+        self.prescaler
+    }
+
+    /// Converts a number of timestamp counts into wall-clock time
+    ///
+    /// The result saturates to `Duration::MAX`
+    pub fn duration(&self, counts: u64) -> Duration {
+        let cycles = u128::from(counts) * u128::from(self.prescaler);
+        let frequency = u128::from(self.frequency);
+
+        let nanos = cycles % frequency * 1_000_000_000 / frequency;
+        u64::try_from(cycles / frequency)
+            .map_or(Duration::MAX, |secs| Duration::new(secs, nanos as u32))
+    }
+}
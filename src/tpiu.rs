@@ -0,0 +1,481 @@
+//! TPIU formatter frames
+//!
+//! When the formatter of the Trace Port Interface Unit (TPIU) is enabled the trace data of all
+//! the trace sources (the ITM, the ETM, etc.) is interleaved in 16-byte frames. Each byte is
+//! tagged with the ID of the trace source that produced it. `Deframer` recovers the bytes of each
+//! trace source so the ITM ones can be fed into a `Decoder` or a `SliceDecoder`. `Framer` does the
+//! opposite: it wraps raw trace data into formatter frames.
+//!
+//! # References
+//!
+//! - [CoreSight Architecture Specification (IHI 0029)][0] - Chapter D4 Trace Formatter
+//!
+//! [0]: https://developer.arm.com/documentation/ihi0029/latest
+//!
+//! ```
+//! use itm::{tpiu::Deframer, Decoder};
+//!
+//! // the ITM is usually configured as trace source 1
+//! const ITM_ID: u8 = 1;
+//!
+//! let mut deframer = Deframer::new();
+//! let mut decoder = Decoder::new();
+//!
+//! deframer.feed(
+//!     &[
+//!         // full synchronization packet
+//!         0xff, 0xff, 0xff, 0x7f, //
+//!         // frame: source 1 writes "hello" in two instrumentation packets, then there's no
+//!         // trace data (source 0)
+//!         0x03, 0x03, b'h', b'e', b'l', b'l', 0x00, b'o', 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+//!         0x00, 0x08,
+//!     ],
+//!     |id, bytes| {
+//!         if id == ITM_ID {
+//!             decoder.feed(bytes);
+//!         }
+//!     },
+//! );
+//!
+//! let text = decoder
+//!     .map(|res| match res.unwrap() {
+//!         itm::Packet::Instrumentation(i) => i.payload().to_vec(),
+//!         _ => vec![],
+//!     })
+//!     .collect::<Vec<_>>()
+//!     .concat();
+//! assert_eq!(text, b"hello");
+//! ```
+
+use crate::SliceDecoder;
+
+/// Size of a formatter frame in bytes
+pub const FRAME_SIZE: usize = 16;
+
+/// Full synchronization packet, as it appears in the byte stream
+///
+/// The formatter emits it between frames; the frame boundaries are found by looking for it
+pub const FULL_SYNC: [u8; 4] = [0xff, 0xff, 0xff, 0x7f];
+
+/// Half-word synchronization packet, as it appears in the byte stream
+///
+/// The formatter can emit it at any even position of a frame, as padding; it's not part of the
+/// frame
+pub const HALF_SYNC: [u8; 2] = [0xff, 0x7f];
+
+/// Trace source ID that carries no trace data
+pub const NULL_ID: u8 = 0x00;
+
+// reserved trace source ID; it only appears in synchronization packets
+const RESERVED_ID: u8 = 0x7f;
+
+/// Demultiplexes the trace data of a TPIU formatted stream by trace source ID
+///
+/// Bytes are pushed into the deframer using the `feed` method, in chunks of arbitrary size. The
+/// deframer discards the input until the first full synchronization packet, which marks the
+/// frame boundaries, and realigns its frames on every synchronization packet it finds later on;
+/// a frame interrupted by a synchronization packet is discarded. Half-word synchronization packets
+/// are skipped.
+///
+/// A 0xff byte may be the start of a synchronization packet, so the deframer holds it back until
+/// the next byte that can't be part of one; a frame that ends in 0xff is reported when the next
+/// byte is fed.
+///
+/// `Deframer` doesn't allocate and is available without the `std` feature.
+#[derive(Clone, Debug)]
+pub struct Deframer {
+    // the frame boundaries are known
+    aligned: bool,
+    // bytes of the frame that's being received
+    frame: [u8; FRAME_SIZE],
+    // trace source ID of the next data byte
+    id: u8,
+    // number of bytes in `frame`
+    len: usize,
+    // number of trailing 0xff bytes that have been received but not added to `frame`, as they may
+    // be the start of a synchronization packet
+    ones: usize,
+}
+
+impl Default for Deframer {
+    fn default() -> Deframer {
+        Deframer::new()
+    }
+}
+
+impl Deframer {
+    /// Creates a deframer that has not been fed any byte
+    pub const fn new() -> Deframer {
+        Deframer {
+            aligned: false,
+            frame: [0; FRAME_SIZE],
+            id: NULL_ID,
+            len: 0,
+            ones: 0,
+        }
+    }
+
+    /// Pushes bytes into the deframer and calls `f` with the trace data of the frames they
+    /// complete
+    ///
+    /// `f` is called with a trace source ID and consecutive bytes produced by that source, in
+    /// stream order. Bytes of the null trace source are not reported.
+    pub fn feed(&mut self, bytes: &[u8], mut f: impl FnMut(u8, &[u8])) {
+        const SYNC_ONES: usize = FULL_SYNC.len() - 1;
+
+        for &byte in bytes {
+            if byte == 0xff {
+                if self.ones == SYNC_ONES {
+                    // the oldest one can't be part of a synchronization packet
+                    self.push(0xff, &mut f);
+                } else {
+                    self.ones += 1;
+                }
+                continue;
+            }
+
+            if byte == RESERVED_ID && self.ones == SYNC_ONES {
+                // the next byte starts a frame; the frame in progress, if any, was interrupted
+                self.aligned = true;
+                self.len = 0;
+                self.ones = 0;
+                continue;
+            }
+
+            if byte == RESERVED_ID && self.ones != 0 {
+                while self.ones > 1 {
+                    self.ones -= 1;
+                    self.push(0xff, &mut f);
+                }
+                self.ones = 0;
+
+                if self.aligned && self.len & 1 == 0 {
+                    // half-word synchronization packet
+                    continue;
+                }
+
+                self.push(0xff, &mut f);
+                self.push(byte, &mut f);
+                continue;
+            }
+
+            while self.ones != 0 {
+                self.ones -= 1;
+                self.push(0xff, &mut f);
+            }
+            self.push(byte, &mut f);
+        }
+    }
+
+    // adds a byte to the frame in progress
+    fn push(&mut self, byte: u8, f: &mut impl FnMut(u8, &[u8])) {
+        if !self.aligned {
+            return;
+        }
+
+        self.frame[self.len] = byte;
+        self.len += 1;
+
+        if self.len == FRAME_SIZE {
+            self.len = 0;
+            let frame = self.frame;
+            self.id = decode_frame(&frame, self.id, &mut *f);
+        }
+    }
+
+    /// Returns the trace source ID of the next data byte
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+}
+
+/// Decodes a formatter frame
+///
+/// `id` is the trace source ID in effect at the start of the frame. `f` is called with the trace
+/// data of the frame as in `Deframer::feed`. Returns the trace source ID in effect at the end of
+/// the frame.
+pub fn decode_frame(frame: &[u8; FRAME_SIZE], mut id: u8, mut f: impl FnMut(u8, &[u8])) -> u8 {
+    // the data of a frame, split into runs of bytes of the same trace source
+    let mut run = [0; FRAME_SIZE - 1];
+    let mut len = 0;
+    let mut run_id = id;
+    let mut push = |id: u8, byte: u8| {
+        if id != run_id {
+            if len != 0 && run_id != NULL_ID && run_id != RESERVED_ID {
+                f(run_id, &run[..len]);
+            }
+
+            run_id = id;
+            len = 0;
+        }
+
+        run[len] = byte;
+        len += 1;
+    };
+
+    // the last byte holds the LSB of the data bytes, or the position of the ID changes, at the
+    // even positions
+    let aux = frame[FRAME_SIZE - 1];
+    for (i, pair) in frame[..FRAME_SIZE - 1].chunks(2).enumerate() {
+        let flag = aux & (1 << i) != 0;
+        let even = pair[0];
+        let odd = pair.get(1).cloned();
+
+        if even & 1 == 0 {
+            // data byte
+            push(id, even | u8::from(flag));
+
+            if let Some(odd) = odd {
+                push(id, odd);
+            }
+        } else {
+            // ID change; the flag says whether it takes effect after the next data byte
+            let new_id = even >> 1;
+
+            match odd {
+                Some(odd) if flag => {
+                    push(id, odd);
+                    id = new_id;
+                }
+                Some(odd) => {
+                    id = new_id;
+                    push(id, odd);
+                }
+                None => id = new_id,
+            }
+        }
+    }
+
+    if len != 0 && run_id != NULL_ID && run_id != RESERVED_ID {
+        f(run_id, &run[..len]);
+    }
+
+    id
+}
+
+/// Wraps trace data into formatter frames
+///
+/// Bytes are pushed into the framer using the `feed` method, tagged with the ID of their trace
+/// source. The output starts with a full synchronization packet, so it can be fed into a
+/// `Deframer`, and continues with the frames. Call `flush` at the end of the input to pad the
+/// last frame with null trace data.
+///
+/// `Framer` doesn't allocate and is available without the `std` feature.
+///
+/// ```
+/// use itm::tpiu::{Deframer, Framer};
+///
+/// let mut framed = vec![];
+/// let mut framer = Framer::new();
+/// framer.feed(1, &[0x01, b'a'], |bytes| framed.extend_from_slice(bytes));
+/// framer.flush(|bytes| framed.extend_from_slice(bytes));
+///
+/// let mut raw = vec![];
+/// Deframer::new().feed(&framed, |id, bytes| {
+///     assert_eq!(id, 1);
+///     raw.extend_from_slice(bytes);
+/// });
+/// assert_eq!(raw, [0x01, b'a']);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Framer {
+    // the frame that's being built; its last byte holds the flags of the even positions
+    frame: [u8; FRAME_SIZE],
+    // trace source ID of the last byte in `frame`
+    id: u8,
+    // number of positions used in `frame`
+    len: usize,
+    // the synchronization packet has been emitted
+    synced: bool,
+}
+
+impl Default for Framer {
+    fn default() -> Framer {
+        Framer::new()
+    }
+}
+
+impl Framer {
+    /// Creates a framer that has not been fed any byte
+    pub const fn new() -> Framer {
+        Framer {
+            frame: [0; FRAME_SIZE],
+            id: NULL_ID,
+            len: 0,
+            synced: false,
+        }
+    }
+
+    /// Pushes the bytes of trace source `id` into the framer and calls `f` with the output they
+    /// complete
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `id` is not a valid trace source ID (`0x01..=0x6f`)
+    pub fn feed(&mut self, id: u8, bytes: &[u8], mut f: impl FnMut(&[u8])) {
+        assert!(
+            (0x01..=0x6f).contains(&id),
+            "invalid trace source ID: {:#04x}",
+            id
+        );
+
+        for &byte in bytes {
+            if id != self.id {
+                self.change_id(id, &mut f);
+            }
+
+            self.push(byte, &mut f);
+        }
+    }
+
+    /// Completes the frame that's being built, if any, with null trace data and calls `f` with
+    /// it
+    pub fn flush(&mut self, mut f: impl FnMut(&[u8])) {
+        if self.len == 0 {
+            return;
+        }
+
+        if self.id != NULL_ID {
+            self.change_id(NULL_ID, &mut f);
+        }
+
+        while self.len != 0 {
+            self.push(0, &mut f);
+        }
+    }
+
+    fn change_id(&mut self, id: u8, f: &mut impl FnMut(&[u8])) {
+        let aux = FRAME_SIZE - 1;
+
+        if self.len & 1 == 0 {
+            // the ID change takes effect immediately
+            self.frame[self.len] = (id << 1) | 1;
+            self.frame[aux] &= !(1 << (self.len / 2));
+            self.len += 1;
+            self.id = id;
+
+            if self.len == aux {
+                self.emit(f);
+            }
+        } else {
+            // odd positions can only hold data: move the previous data byte here and put the ID
+            // change before it, flagged to take effect after it
+            let even = self.len - 1;
+            let flag = 1 << (even / 2);
+            let byte = self.frame[even] | u8::from(self.frame[aux] & flag != 0);
+
+            self.frame[even] = (id << 1) | 1;
+            self.frame[aux] |= flag;
+            self.frame[self.len] = byte;
+            self.len += 1;
+            self.id = id;
+        }
+    }
+
+    fn push(&mut self, byte: u8, f: &mut impl FnMut(&[u8])) {
+        let aux = FRAME_SIZE - 1;
+
+        if self.len & 1 == 0 {
+            // the LSB goes into the flags
+            self.frame[self.len] = byte & !1;
+            if byte & 1 == 0 {
+                self.frame[aux] &= !(1 << (self.len / 2));
+            } else {
+                self.frame[aux] |= 1 << (self.len / 2);
+            }
+        } else {
+            self.frame[self.len] = byte;
+        }
+
+        self.len += 1;
+        if self.len == aux {
+            self.emit(f);
+        }
+    }
+
+    fn emit(&mut self, f: &mut impl FnMut(&[u8])) {
+        if !self.synced {
+            self.synced = true;
+            f(&FULL_SYNC);
+        }
+
+        f(&self.frame);
+        self.frame = [0; FRAME_SIZE];
+        self.len = 0;
+    }
+}
+
+/// The framing of a trace capture
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Format {
+    /// Raw ITM bytes; decode them with a `Decoder` or a `SliceDecoder`
+    #[default]
+    Raw,
+    /// TPIU formatter frames; demultiplex them with a `Deframer`
+    Tpiu,
+}
+
+/// Guesses the framing of a capture from its first bytes
+///
+/// This looks for synchronization packets. A full TPIU synchronization packet followed by
+/// well-formed frames means TPIU formatter frames. TPIU frames carry ITM synchronization packets
+/// (five or more zero bytes followed by 0x80) unchanged, so one only means raw ITM data if the
+/// bytes after it decode as ITM packets and no TPIU synchronization packet is found in `bytes`.
+/// Returns `None` if neither is found, in which case more bytes are needed to decide, or the target
+/// has not been configured to emit synchronization packets.
+///
+/// ```
+/// use itm::tpiu::{self, Format};
+///
+/// assert_eq!(tpiu::detect(&[0x01, b'a', 0, 0, 0, 0, 0, 0x80]), Some(Format::Raw));
+/// assert_eq!(tpiu::detect(&[0x01, b'a']), None);
+/// ```
+pub fn detect(bytes: &[u8]) -> Option<Format> {
+    let mut raw = false;
+    let mut zeros = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        if !raw && byte == 0b1000_0000 && zeros >= 5 {
+            raw = SliceDecoder::new(&bytes[i + 1..]).all(|res| res.is_ok());
+        }
+
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+
+        if i >= 3 && bytes[i - 3..=i] == FULL_SYNC && is_framed(&bytes[i + 1..]) {
+            return Some(Format::Tpiu);
+        }
+    }
+
+    if raw {
+        Some(Format::Raw)
+    } else {
+        None
+    }
+}
+
+// checks that `bytes` is a sequence of frames and synchronization packets, up to the end of the
+// last complete frame
+fn is_framed(mut bytes: &[u8]) -> bool {
+    let mut frames = 0;
+    loop {
+        if bytes.starts_with(&FULL_SYNC) {
+            bytes = &bytes[FULL_SYNC.len()..];
+        } else if bytes.starts_with(&HALF_SYNC) {
+            bytes = &bytes[HALF_SYNC.len()..];
+        } else if bytes.len() >= FRAME_SIZE {
+            // an ID change to the reserved ID only appears in synchronization packets
+            if bytes[..FRAME_SIZE - 1]
+                .iter()
+                .step_by(2)
+                .any(|&b| b == 0xff)
+            {
+                return false;
+            }
+
+            bytes = &bytes[FRAME_SIZE..];
+            frames += 1;
+        } else {
+            // need at least one complete frame to decide
+            return frames != 0;
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! WebAssembly bindings for the `itm` crate
+//!
+//! Build with `wasm-pack build wasm`. Usage from JavaScript:
+//!
+//! ``` js
+//! import { Decoder } from "itm-wasm";
+//!
+//! const decoder = new Decoder();
+//! socket.onmessage = (event) => {
+//!     for (const packet of decoder.feed(new Uint8Array(event.data))) {
+//!         if (packet.Instrumentation) {
+//!             console.log(packet.Instrumentation.port, packet.Instrumentation.payload);
+//!         }
+//!     }
+//! };
+//! ```
+//!
+//! Packets use the `serde` representation of `itm::Packet`. Decoding errors are reported as
+//! `{ error: "<message>" }` objects.
+
+#![deny(missing_docs)]
+#![deny(warnings)]
+
+use itm::{Error, Packet};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// A push-based ITM packet decoder
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct Decoder {
+    decoder: itm::Decoder,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Item {
+    Packet(Packet),
+    Error { error: String },
+}
+
+impl From<Result<Packet, Error>> for Item {
+    fn from(res: Result<Packet, Error>) -> Item {
+        match res {
+            Ok(packet) => Item::Packet(packet),
+            Err(e) => Item::Error {
+                error: e.to_string(),
+            },
+        }
+    }
+}
+
+fn to_js(res: Result<Packet, Error>) -> Result<JsValue, JsValue> {
+    Ok(serde_wasm_bindgen::to_value(&Item::from(res))?)
+}
+
+#[wasm_bindgen]
+impl Decoder {
+    /// Creates a new decoder
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Decoder {
+        Decoder::default()
+    }
+
+    /// Feeds bytes into the decoder and returns the packets they complete
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<JsValue>, JsValue> {
+        self.decoder.feed(bytes);
+
+        (&mut self.decoder).map(to_js).collect()
+    }
+
+    /// Signals the end of the input; returns an error object if the decoder was holding an
+    /// incomplete packet
+    pub fn finish(&mut self) -> Result<Option<JsValue>, JsValue> {
+        self.decoder.finish().map(|e| to_js(Err(e))).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use itm::{packet::Instrumentation, Error, Packet};
+    use serde_json::json;
+
+    use crate::Item;
+
+    // `serde_wasm_bindgen` produces the JavaScript equivalent of the JSON representation
+    fn to_json(res: Result<Packet, Error>) -> serde_json::Value {
+        serde_json::to_value(Item::from(res)).unwrap()
+    }
+
+    #[test]
+    fn packet() {
+        let packet = Packet::Instrumentation(Instrumentation::new(1, b"ab"));
+        assert_eq!(
+            to_json(Ok(packet)),
+            json!({ "Instrumentation": { "page": 0, "payload": [97, 98], "port": 1 } })
+        );
+
+        assert_eq!(to_json(Ok(Packet::Overflow)), json!("Overflow"));
+    }
+
+    #[test]
+    fn error() {
+        let error = Error::ReservedHeader { byte: 0x04 };
+        let message = error.to_string();
+        assert_eq!(to_json(Err(error)), json!({ "error": message }));
+    }
+}